@@ -0,0 +1,102 @@
+//! A renderer for terminals, using ANSI escape codes to color the ranges.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+
+use proc_macro2::TokenStream;
+
+use crate::{SpanMap, Spanr};
+
+/// Background colors from the 256-color palette, cycled through by range
+/// index. All are light enough to be read with black text.
+const PALETTE: &[u8] = &[153, 194, 224, 230, 189, 217, 159, 187, 223, 157];
+
+const RESET: &str = "\x1b[0m";
+
+/// Writes `text`, colored by the last of `indexes` if `color` is set.
+fn write_chunk(res: &mut String, text: &str, indexes: &[usize], color: bool) {
+    match indexes.iter().max() {
+        Some(idx) if color && text != "\n" => {
+            let background = PALETTE[idx % PALETTE.len()];
+            res.push_str(&format!("\x1b[30;48;5;{}m{}{}", background, text, RESET));
+        }
+        _ => res.push_str(text),
+    }
+}
+
+impl SpanMap {
+    /// Renders the generated code for a terminal, with each range given a
+    /// background color, optionally followed by the source code colored the
+    /// same way.
+    ///
+    /// If `color` is `false`, the text is written without escape codes.
+    pub fn to_ansi(&self, show_source: bool, color: bool) -> String {
+        let mut res = String::new();
+        for (text, idx) in self.generated() {
+            write_chunk(&mut res, text, idx.as_slice(), color);
+        }
+        if show_source {
+            for (text, indexes) in self.source_parts() {
+                write_chunk(&mut res, text, indexes, color);
+            }
+        }
+        res
+    }
+}
+
+/// Returns whether output to stdout should be colored: only if it is a
+/// terminal, and the `NO_COLOR` environment variable isn't set.
+fn stdout_supports_color() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal()
+}
+
+impl Spanr {
+    /// Renders the span mapping of a token stream for a terminal, using ANSI
+    /// escape codes to color each range, optionally followed by the source
+    /// code.
+    pub fn to_ansi_string(&self, token_stream: TokenStream, show_source: bool) -> String {
+        self.build_span_map(token_stream).to_ansi(show_source, true)
+    }
+    /// Prints the span mapping of a token stream to stdout, optionally
+    /// followed by the source code.
+    ///
+    /// Ranges are only colored if stdout is a terminal and the `NO_COLOR`
+    /// environment variable isn't set.
+    pub fn print_ansi(&self, token_stream: TokenStream, show_source: bool) {
+        let span_map = self.build_span_map(token_stream);
+        let text = span_map.to_ansi(show_source, stdout_supports_color());
+        let _ = io::stdout().lock().write_all(text.as_bytes());
+    }
+}
+
+/// Renders the span mapping of a token stream, followed by the source code,
+/// for a terminal, using ANSI escape codes to color each range.
+pub fn to_ansi_string(token_stream: TokenStream) -> String {
+    Spanr::default().to_ansi_string(token_stream, true)
+}
+
+/// Prints the span mapping of a token stream, followed by the source code, to
+/// stdout, coloring each range if stdout is a terminal.
+pub fn print_ansi(token_stream: TokenStream) {
+    Spanr::default().print_ansi(token_stream, true)
+}
+
+#[cfg(all(test, feature = "span-locations"))]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn ansi_colors() {
+        let src = "fn foo() {}";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        let colored = span_map.to_ansi(false, true);
+        assert!(colored.starts_with("\x1b[30;48;5;153mfn\x1b[0m \x1b[30;48;5;194mfoo\x1b[0m"));
+        assert_eq!(span_map.to_ansi(false, false), "fn foo() {\n}\n");
+        let with_source = span_map.to_ansi(true, false);
+        assert!(with_source
+            .ends_with("// foo.rs\n//======================================\nfn foo() {}\n"));
+    }
+}
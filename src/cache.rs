@@ -0,0 +1,87 @@
+//! Caching of source files read while generating reports.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::split_lines;
+
+/// A cache of the source files read while generating reports, shared by
+/// every [`Spanr`](crate::Spanr) it is given to.
+///
+/// A cached file is read again if its modification time has changed since
+/// it was last read. Clones of a cache share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct SourceCache {
+    entries: Arc<Mutex<HashMap<PathBuf, CacheEntry>>>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    modified: Option<SystemTime>,
+    lines: Arc<[String]>,
+}
+
+impl SourceCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns the lines of a source file, reading it only if it isn't
+    /// cached or has been modified since it was cached.
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Arc<[String]>> {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.get(path) {
+            if entry.modified.is_some() && entry.modified == modified {
+                return Ok(entry.lines.clone());
+            }
+        }
+        let lines: Arc<[String]> = split_lines(&fs::read_to_string(path)?).into();
+        entries.insert(
+            path.into(),
+            CacheEntry {
+                modified,
+                lines: lines.clone(),
+            },
+        );
+        Ok(lines)
+    }
+    /// Returns the number of files in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn invalidated_by_mtime() {
+        let path = std::env::temp_dir().join(format!("spanr-{}-cache.rs", std::process::id()));
+        fs::write(&path, "a\nb\n").unwrap();
+        let cache = super::SourceCache::new();
+        assert_eq!(&*cache.read(&path).unwrap(), ["a", "b"]);
+
+        // Unchanged modification times are served from the cache.
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        fs::write(&path, "c\n").unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(&*cache.read(&path).unwrap(), ["a", "b"]);
+
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(&*cache.read(&path).unwrap(), ["c"]);
+        assert_eq!(cache.len(), 1);
+    }
+}
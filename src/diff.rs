@@ -0,0 +1,284 @@
+//! Reports comparing the generated code of two token streams.
+
+use std::io::Write;
+
+use proc_macro2::TokenStream;
+
+use crate::{generate_ranges, Chunk, Ranges, Spanr, SpanrError};
+
+/// How a token differs between the two token streams being compared.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Change {
+    Unchanged,
+    Added,
+    Removed,
+}
+
+impl Change {
+    /// The CSS class applied to tokens with this change.
+    fn class(self) -> &'static str {
+        match self {
+            Change::Unchanged => "diff-eq",
+            Change::Added => "diff-ins",
+            Change::Removed => "diff-del",
+        }
+    }
+}
+
+/// Computes a longest common subsequence diff of `before` and `after`.
+fn diff<T: PartialEq>(before: &[T], after: &[T]) -> Vec<Change> {
+    let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut res = vec![Change::Unchanged; prefix];
+    diff_middle(
+        &before[prefix..before.len() - suffix],
+        &after[prefix..after.len() - suffix],
+        &mut res,
+    );
+    res.extend(std::iter::repeat_n(Change::Unchanged, suffix));
+    res
+}
+
+/// Returns the length of the longest common subsequence of `before` and each
+/// prefix of `after`, by the length of the prefix.
+fn lcs_lengths<T: PartialEq>(before: &[T], after: &[T]) -> Vec<usize> {
+    let mut lengths = vec![0; after.len() + 1];
+    let mut prev = lengths.clone();
+    for a in before {
+        std::mem::swap(&mut lengths, &mut prev);
+        for (j, b) in after.iter().enumerate() {
+            lengths[j + 1] = if a == b {
+                prev[j] + 1
+            } else {
+                prev[j + 1].max(lengths[j])
+            };
+        }
+    }
+    lengths
+}
+
+/// Appends the changes turning `before` into `after` to `res`.
+///
+/// Uses Hirschberg's algorithm, which only needs space linear in the length
+/// of the inputs, as the expansions being compared can be very large. Where
+/// a token is replaced, its removal comes before the addition.
+fn diff_middle<T: PartialEq>(before: &[T], after: &[T], res: &mut Vec<Change>) {
+    match before {
+        [] => res.extend(std::iter::repeat_n(Change::Added, after.len())),
+        _ if after.is_empty() => res.extend(std::iter::repeat_n(Change::Removed, before.len())),
+        [token] => match after.iter().position(|other| other == token) {
+            Some(j) => {
+                res.extend(std::iter::repeat_n(Change::Added, j));
+                res.push(Change::Unchanged);
+                res.extend(std::iter::repeat_n(Change::Added, after.len() - j - 1));
+            }
+            None => {
+                res.push(Change::Removed);
+                res.extend(std::iter::repeat_n(Change::Added, after.len()));
+            }
+        },
+        _ => {
+            // Splits `after` where the halves of `before` have the longest
+            // common subsequence with the parts of it either side.
+            let mid = before.len() / 2;
+            let forward = lcs_lengths(&before[..mid], after);
+            let before_rev: Vec<&T> = before[mid..].iter().rev().collect();
+            let after_rev: Vec<&T> = after.iter().rev().collect();
+            let backward = lcs_lengths(&before_rev, &after_rev);
+            let split = (0..=after.len())
+                .max_by_key(|&j| (forward[j] + backward[after.len() - j], std::cmp::Reverse(j)))
+                .unwrap_or(0);
+            diff_middle(&before[..mid], &after[..split], res);
+            diff_middle(&before[mid..], &after[split..], res);
+        }
+    }
+}
+
+/// Combines the generated code of two token streams into one, following the
+/// layout of `after` with the tokens removed from `before` written inline.
+///
+/// Returns the combined ranges, along with the change of each generated
+/// chunk.
+fn merge(before: Ranges, after: Ranges) -> (Ranges, Vec<Option<Change>>) {
+    let mut res = Ranges::default();
+    let mut changes = Vec::new();
+    let mut push = |ranges: &Ranges, chunk: &Chunk, change| {
+        let range = chunk.range.map(|idx| {
+            let range = ranges.range_list[idx];
+            let path = ranges.source_list[range.source].clone();
+            res.add_range(path, range.start, range.end)
+        });
+        res.generated.push(Chunk {
+            text: chunk.text.clone(),
+            range,
+            highlight: chunk.highlight,
+            // Blocks are numbered independently in each stream.
+            block: None,
+            layout: chunk.layout,
+        });
+        changes.push(change);
+    };
+
+    let tokens = |ranges: &Ranges| -> Vec<usize> {
+        (0..ranges.generated.len())
+            .filter(|&idx| !ranges.generated[idx].is_layout())
+            .collect()
+    };
+    let before_tokens = tokens(&before);
+    let after_tokens = tokens(&after);
+    let text = |ranges: &Ranges, tokens: &[usize]| -> Vec<String> {
+        tokens
+            .iter()
+            .map(|&idx| ranges.generated[idx].text.clone())
+            .collect()
+    };
+    let changes_by_token = diff(&text(&before, &before_tokens), &text(&after, &after_tokens));
+
+    let (mut before_pos, mut after_pos, mut after_chunk) = (0, 0, 0);
+    for change in changes_by_token {
+        if change == Change::Removed {
+            // Removed tokens go after the layout of `after` leading up to its
+            // next token, so that they start on the same line.
+            while after
+                .generated
+                .get(after_chunk)
+                .is_some_and(Chunk::is_layout)
+            {
+                push(&after, &after.generated[after_chunk], None);
+                after_chunk += 1;
+            }
+            push(
+                &before,
+                &before.generated[before_tokens[before_pos]],
+                Some(change),
+            );
+            let space = Chunk {
+                text: " ".into(),
+                range: None,
+                highlight: None,
+                block: None,
+                layout: true,
+            };
+            push(&before, &space, None);
+            before_pos += 1;
+            continue;
+        }
+        // Write out the layout of `after` leading up to the token.
+        let token = after_tokens[after_pos];
+        for chunk in &after.generated[after_chunk..=token] {
+            let chunk_change = if chunk.is_layout() {
+                None
+            } else {
+                Some(change)
+            };
+            push(&after, chunk, chunk_change);
+        }
+        after_chunk = token + 1;
+        after_pos += 1;
+        if change == Change::Unchanged {
+            before_pos += 1;
+        }
+    }
+    for chunk in &after.generated[after_chunk..] {
+        push(&after, chunk, None);
+    }
+    res.sort();
+    (res, changes)
+}
+
+impl Spanr {
+    /// Renders a comparison of the generated code of two token streams as an
+    /// HTML document.
+    ///
+    /// Tokens are tagged with the `diff-eq`, `diff-ins` or `diff-del` CSS
+    /// classes depending on whether they are unchanged, only in `after`, or
+    /// only in `before`. Tokens from both streams remain mapped back to their
+    /// sources.
+    pub fn build_diff_html(
+        &self,
+        before: TokenStream,
+        after: TokenStream,
+    ) -> Result<String, SpanrError> {
+        let mut res = Vec::new();
+        self.write_diff_html(before, after, &mut res)?;
+        Ok(String::from_utf8(res).expect("HTML output is always valid UTF-8"))
+    }
+    /// Renders a comparison of the generated code of two token streams as an
+    /// HTML document, streaming it to `out` as it is produced.
+    pub fn write_diff_html<W: Write>(
+        &self,
+        before: TokenStream,
+        after: TokenStream,
+        out: W,
+    ) -> Result<(), SpanrError> {
+        let (ranges, changes) = merge(generate_ranges(before, self), generate_ranges(after, self));
+        self.write_report(ranges, |idx, _| changes[idx].map(Change::class), false, out)
+    }
+}
+
+/// Renders a comparison of the generated code of two token streams as an
+/// HTML document.
+pub fn generate_diff_html(before: TokenStream, after: TokenStream) -> Result<String, SpanrError> {
+    Spanr::default().build_diff_html(before, after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Change::*;
+
+    #[test]
+    fn token_diff() {
+        let diff = |a: &str, b: &str| {
+            let a: Vec<_> = a.split(' ').collect();
+            let b: Vec<_> = b.split(' ').collect();
+            super::diff(&a, &b)
+        };
+        assert_eq!(diff("a b c", "a b c"), [Unchanged; 3]);
+        assert_eq!(
+            diff("a b c d", "a x c d y"),
+            [Unchanged, Removed, Added, Unchanged, Unchanged, Added]
+        );
+        assert_eq!(diff("a b", "b"), [Removed, Unchanged]);
+        assert_eq!(
+            diff("a b c d e", "x b d y"),
+            [Removed, Added, Unchanged, Removed, Unchanged, Removed, Added]
+        );
+
+        // Inputs long enough to be split many times still find the longest
+        // common subsequence.
+        let before: Vec<usize> = (0..3_000).collect();
+        let after: Vec<usize> = (0..3_000).map(|i| i * 2).collect();
+        let changes = super::diff(&before, &after);
+        let count = |change| changes.iter().filter(|&&c| c == change).count();
+        assert_eq!(count(Unchanged), 1_500);
+        assert_eq!(count(Removed), 1_500);
+        assert_eq!(count(Added), 1_500);
+    }
+
+    #[test]
+    fn merged_layout() {
+        let config = crate::Spanr::new();
+        let ranges = |src: &str| crate::generate_ranges(src.parse().unwrap(), &config);
+        let (merged, changes) =
+            super::merge(ranges("fn foo() { a; b; }"), ranges("fn foo() { a; c; }"));
+        let text: String = merged
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(text, "fn foo() {\n    a;\n    b c;\n}\n");
+        let removed: Vec<_> = merged
+            .generated
+            .iter()
+            .zip(&changes)
+            .filter(|(_, change)| **change == Some(Removed))
+            .map(|(chunk, _)| chunk.text.as_str())
+            .collect();
+        assert_eq!(removed, ["b"]);
+    }
+}
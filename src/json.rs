@@ -0,0 +1,138 @@
+//! A JSON representation of the span mapping, for consumption by renderers
+//! written in other languages.
+//!
+//! The schema is described by the types in this module. Fields are only ever
+//! added, and [`SCHEMA_VERSION`] is bumped whenever an existing field changes.
+
+use serde::Serialize;
+
+use crate::{GeneratedChunk, Position, SpanMap};
+
+/// The version of the schema described by this module.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The root of the JSON document.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonSpanMap {
+    /// Always [`SCHEMA_VERSION`].
+    pub version: u32,
+    /// The referenced source files, indexed by source index.
+    pub sources: Vec<String>,
+    /// The distinct source ranges, indexed by range index.
+    pub ranges: Vec<JsonRange>,
+    /// The chunks of pretty-printed generated code, in order.
+    pub generated: Vec<JsonChunk>,
+}
+
+/// A spanned region of a source file.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRange {
+    /// The index of the source file in [`JsonSpanMap::sources`].
+    pub source: usize,
+    pub start: JsonPosition,
+    pub end: JsonPosition,
+}
+
+/// A location within a source file, with a 1-based line and a 0-based
+/// column counted in `char`s.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct JsonPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A chunk of pretty-printed generated code.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonChunk {
+    pub text: String,
+    /// The index of the range in [`JsonSpanMap::ranges`] this chunk was
+    /// spanned to, or `null` if it has no span.
+    pub range: Option<usize>,
+    /// Whether the chunk was inserted by the pretty-printer, such as
+    /// whitespace or a label, rather than written for a token.
+    pub layout: bool,
+}
+
+impl From<Position> for JsonPosition {
+    fn from(other: Position) -> Self {
+        Self {
+            line: other.line,
+            column: other.column,
+        }
+    }
+}
+
+impl From<&SpanMap> for JsonSpanMap {
+    fn from(span_map: &SpanMap) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            sources: span_map
+                .sources()
+                .map(|(_, path)| path.display().to_string())
+                .collect(),
+            ranges: span_map
+                .ranges()
+                .map(|(_, range)| JsonRange {
+                    source: range.source,
+                    start: range.start.into(),
+                    end: range.end.into(),
+                })
+                .collect(),
+            generated: span_map
+                .chunks()
+                .map(|chunk| JsonChunk {
+                    text: chunk.text().into(),
+                    range: match chunk {
+                        GeneratedChunk::Spanned(_, idx) => Some(idx),
+                        _ => None,
+                    },
+                    layout: matches!(chunk, GeneratedChunk::Layout(_)),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SpanMap {
+    /// Serializes this span mapping as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&JsonSpanMap::from(self)).expect("span maps are always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn json_schema() {
+        let src = "foo(1)";
+        let span_map = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(src.parse().unwrap());
+        let json: serde_json::Value = serde_json::from_str(&span_map.to_json()).unwrap();
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["sources"], serde_json::json!(["foo.rs"]));
+        let range = |start, end| {
+            serde_json::json!({
+                "source": 0,
+                "start": { "line": 1, "column": start },
+                "end": { "line": 1, "column": end },
+            })
+        };
+        assert_eq!(
+            json["ranges"],
+            serde_json::json!([range(0, 3), range(3, 4), range(4, 5), range(5, 6)])
+        );
+        assert_eq!(
+            json["generated"][0],
+            serde_json::json!({ "text": "foo", "range": 0, "layout": false })
+        );
+
+        // Tokens without a span have no range.
+        let x: TokenStream = src.parse().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&crate::to_json(x)).unwrap();
+        assert_eq!(json["sources"], serde_json::json!([]));
+        assert_eq!(json["generated"][0]["range"], serde_json::Value::Null);
+    }
+}
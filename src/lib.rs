@@ -1,18 +1,121 @@
-use std::collections::{HashMap, HashSet};
+//! Visualizes the spans of the tokens generated by procedural macros.
+//!
+//! The main entry points render a token stream as an HTML report, with the
+//! pretty-printed generated code on the left and the source code its tokens
+//! were spanned to on the right. The same mapping is available as data
+//! through [`SpanMap`], for writing other renderers and analyses:
+//!
+//! - [`SpanMap::sources`] lists the source files referenced, by index.
+//! - [`SpanMap::ranges`] lists the distinct [`Range`]s of source code that
+//!   tokens were spanned to, by index.
+//! - [`SpanMap::generated`] walks the pretty-printed generated code, giving
+//!   the index of the range each chunk was spanned to.
+//! - [`SpanMap::chunks`] walks the same chunks, telling tokens without a span
+//!   apart from the layout inserted by the pretty-printer.
+//!
+//! ```
+//! let tokens: proc_macro2::TokenStream = "fn foo() {}".parse().unwrap();
+//! let span_map = spanr::build_span_map(tokens);
+//! for (text, range) in span_map.generated() {
+//!     if let Some((path, start, end)) = range.and_then(|idx| span_map.resolve(idx)) {
+//!         println!("{:?} came from {}:{}:{}", text, path.display(), start.line, start.column);
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "proc-macro")]
+extern crate proc_macro;
+
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display};
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use html_escape::encode_text;
-use proc_macro2::{Delimiter, LineColumn, Spacing, Span, TokenStream, TokenTree};
+#[cfg(feature = "span-locations")]
+use proc_macro2::LineColumn;
+use proc_macro2::TokenStream;
+
+mod ansi;
+mod cache;
+mod diff;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod lsp;
+mod markdown;
+#[cfg(feature = "prettyplease")]
+mod pretty;
+mod render;
+mod source;
+mod source_map;
+mod stream;
+mod styled;
+mod svg;
+mod visitor;
+
+use render::{
+    format_timestamp, write_html_parts, write_nested_parts, write_range_class, write_template,
+};
+use source::{
+    advance, canonical_path, load_original_source, read_source, slice_range, split_lines,
+    suspicious_spans_of, uncovered_spans_of, SharedSourceProvider, SourceParts,
+};
+use visitor::{generate_ranges, generate_ranges_multi, TokenVisitor};
+
+pub use ansi::{print_ansi, to_ansi_string};
+pub use cache::SourceCache;
+pub use diff::generate_diff_html;
+pub use lsp::to_lsp_locations;
+pub use markdown::to_markdown;
+pub use source::SourceProvider;
+pub use source_map::to_source_map;
+pub use stream::stream_html;
+pub use styled::{to_styled_lines, StyledSpan};
+pub use svg::generate_svg;
 
 const TEMPLATE_HTML: &str = include_str!("template.html");
+const STYLE_CSS: &str = include_str!("style.css");
+const SCRIPT_JS: &str = include_str!("script.js");
+
+/// The search box written in place of the `{SEARCH}` placeholder.
+const SEARCH_HTML: &str = "<input id=\"search\" type=\"search\" placeholder=\"Search\">";
+
+/// Expands to [`SECTION_RULE`], for building other constants with `concat!`.
+macro_rules! section_rule {
+    () => {
+        "//======================================"
+    };
+}
+
+/// The line written above and below the header of each section of a report.
+const SECTION_RULE: &str = section_rule!();
+
+/// The default header of each source file in the source panel, in which
+/// `{path}` is replaced with the path of the file.
+const SOURCE_HEADER: &str = concat!(section_rule!(), "\n// {path}\n", section_rule!());
+
+/// Placeholders that every template must contain.
+const REQUIRED_PLACEHOLDERS: &[&str] = &["LEFT", "RIGHT"];
 
+/// A location within a source file.
+///
+/// Lines are 1-based and columns are 0-based, matching `proc_macro2::LineColumn`.
+/// Columns count `char`s, not bytes.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-struct Position {
-    line: usize,
-    column: usize,
+pub struct Position {
+    /// The 1-based line number.
+    pub line: usize,
+    /// The 0-based column, counted in `char`s.
+    pub column: usize,
 }
 
+#[cfg(feature = "span-locations")]
 impl From<LineColumn> for Position {
     fn from(other: LineColumn) -> Self {
         Self {
@@ -22,326 +125,3814 @@ impl From<LineColumn> for Position {
     }
 }
 
+/// A spanned region of a source file.
+///
+/// Ranges order by source, then by position.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-enum SeparatorType {
-    Start,
-    End,
+pub struct Range {
+    /// The index of the source file in the [`SpanMap`] this range belongs
+    /// to.
+    pub source: usize,
+    /// The position of the first character of the range.
+    pub start: Position,
+    /// The position just past the last character of the range.
+    pub end: Position,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-struct RangeSeparator {
-    pos: Position,
-    sep: SeparatorType,
-    idx: usize,
-}
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-struct Range {
-    source: usize,
-    start: Position,
-    end: Position,
+impl Range {
+    /// Returns whether `other` lies entirely within this range, in the same
+    /// source. Every range contains itself, and a zero-width range at either
+    /// end of a range is within it.
+    pub fn contains(&self, other: &Range) -> bool {
+        self.source == other.source && self.start <= other.start && other.end <= self.end
+    }
+    /// Compares ranges in nesting order: by source, then by start, with
+    /// ranges that start together ordered from the longest to the shortest.
+    ///
+    /// Sorting ranges in this order puts each one after every range that
+    /// contains it, so that they can be built into a tree by keeping a stack
+    /// of the ranges containing the current one. Ranges that only partly
+    /// overlap are just ordered by where they start.
+    pub fn cmp_nesting(&self, other: &Range) -> Ordering {
+        let key = |range: &Range| (range.source, range.start, Reverse(range.end));
+        key(self).cmp(&key(other))
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 struct Ranges {
     sources: HashMap<PathBuf, usize>,
     ranges: HashMap<Range, usize>,
-    generated: Vec<(String, Option<usize>)>,
+    source_list: Vec<PathBuf>,
+    range_list: Vec<Range>,
+    generated: Vec<Chunk>,
+    /// The canonical paths of files outside the source root that spans were
+    /// left out for.
+    outside_root: BTreeSet<PathBuf>,
+}
+
+/// A piece of the pretty-printed generated code.
+#[derive(Debug, Clone)]
+struct Chunk {
+    text: String,
+    /// The source range the chunk was generated from, if any.
+    range: Option<usize>,
+    /// The lexical class of the token the chunk was written for, if any.
+    highlight: Option<Highlight>,
+    /// The index of the brace-delimited block this chunk opens or closes,
+    /// if it is one of the braces.
+    block: Option<usize>,
+    /// Whether the chunk was inserted by the pretty-printer, such as
+    /// whitespace or a label, rather than written for a token.
+    layout: bool,
 }
 
+impl Chunk {
+    /// Returns whether the chunk only lays out the generated code, rather
+    /// than being part of a token.
+    fn is_layout(&self) -> bool {
+        self.layout
+    }
+}
+
+/// A lexical class of token, used for syntax highlighting.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum NeedsSpace {
-    Never,
-    Always,
-    IfNotPunct,
+enum Highlight {
+    Keyword,
+    Str,
+    Num,
+    Lifetime,
 }
 
-#[derive(Debug, Clone)]
-struct TokenVisitor {
-    ranges: Ranges,
-    indent: usize,
-    newline: bool,
-    needs_space: NeedsSpace,
-}
-
-impl TokenVisitor {
-    fn add_span(&mut self, span: Span) -> Option<usize> {
-        let source_file = span.source_file();
-        if source_file.is_real() {
-            let num_sources = self.ranges.sources.len();
-            let source = *self
-                .ranges
-                .sources
-                .entry(source_file.path())
-                .or_insert(num_sources);
-            let range = Range {
-                source,
-                start: span.start().into(),
-                end: span.end().into(),
-            };
-            let num_ranges = self.ranges.ranges.len();
-            let range_idx = *self.ranges.ranges.entry(range).or_insert(num_ranges);
-            Some(range_idx)
+impl Highlight {
+    /// The CSS class applied to tokens of this class.
+    fn class(self) -> &'static str {
+        match self {
+            Highlight::Keyword => "kw",
+            Highlight::Str => "lit-str",
+            Highlight::Num => "lit-num",
+            Highlight::Lifetime => "lifetime",
+        }
+    }
+
+    /// Classifies a literal by its first character: numbers start with a
+    /// digit, and everything else is a string, byte string or character.
+    fn of_literal(lit: &str) -> Highlight {
+        if lit.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            Highlight::Num
         } else {
-            None
+            Highlight::Str
+        }
+    }
+}
+
+impl Ranges {
+    /// Renumbers sources in order of their path, and ranges in order of their
+    /// source and position, so that indexes don't depend on the order in
+    /// which spans were encountered.
+    fn sort(&mut self) {
+        let mut source_order: Vec<usize> = (0..self.source_list.len()).collect();
+        source_order.sort_by(|&a, &b| self.source_list[a].cmp(&self.source_list[b]));
+        let mut new_sources = vec![0; source_order.len()];
+        for (new, &old) in source_order.iter().enumerate() {
+            new_sources[old] = new;
+        }
+        self.source_list = source_order
+            .iter()
+            .map(|&old| self.source_list[old].clone())
+            .collect();
+        for range in &mut self.range_list {
+            range.source = new_sources[range.source];
+        }
+
+        let mut range_order: Vec<usize> = (0..self.range_list.len()).collect();
+        range_order.sort_by_key(|&idx| self.range_list[idx]);
+        let mut new_indexes = vec![0; range_order.len()];
+        for (new, &old) in range_order.iter().enumerate() {
+            new_indexes[old] = new;
+        }
+        self.range_list = range_order
+            .iter()
+            .map(|&old| self.range_list[old])
+            .collect();
+        for chunk in &mut self.generated {
+            if let Some(idx) = &mut chunk.range {
+                *idx = new_indexes[*idx];
+            }
         }
+
+        self.sources = self
+            .source_list
+            .iter()
+            .enumerate()
+            .map(|(source, path)| (path.clone(), source))
+            .collect();
+        self.ranges = self
+            .range_list
+            .iter()
+            .enumerate()
+            .map(|(idx, &range)| (range, idx))
+            .collect();
     }
-    fn add_str(&mut self, s: &str, range_idx: Option<usize>) {
-        self.ranges.generated.push((s.into(), range_idx));
+    /// Hashes the path and extent of a range, so that the same span of
+    /// source code hashes the same regardless of the other ranges present.
+    ///
+    /// This uses FNV-1a, which unlike the standard library's hasher is
+    /// guaranteed to be stable across releases.
+    fn range_hash(&self, idx: usize) -> u64 {
+        let range = self.range_list[idx];
+        let path = self.source_list[range.source].to_string_lossy();
+        let positions = [
+            range.start.line,
+            range.start.column,
+            range.end.line,
+            range.end.column,
+        ];
+        let bytes = path
+            .bytes()
+            .chain(positions.iter().flat_map(|n| (*n as u64).to_le_bytes()));
+        bytes.fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        })
+    }
+    /// Picks the background color of each range, as its hue and how many
+    /// steps darker than the base lightness it is.
+    ///
+    /// Each source has its own base hue, and its ranges are given variations
+    /// of that hue in order of their position in the source, so a given span
+    /// has the same color whatever else is in the report.
+    fn range_colors(&self) -> Vec<(f64, usize)> {
+        let mut range_counts = vec![0; self.source_list.len()];
+        self.range_list
+            .iter()
+            .map(|range| {
+                let index = range_counts[range.source];
+                range_counts[range.source] += 1;
+                let hue =
+                    (range.source as f64 * 137.508) % 360.0 + ((index % 5) as f64 - 2.0) * 8.0;
+                (hue, index % 3)
+            })
+            .collect()
     }
-    fn visit_str(&mut self, s: &str, span: Span) {
-        if s.is_empty() {
-            return;
+    /// Orders the rows of generated code by the earliest source position any
+    /// of their chunks were spanned to, returning the index of each chunk in
+    /// its new order, with `None` for where a line break must be added.
+    ///
+    /// Rows with no spanned chunks stay after the row before them, and the
+    /// order of rows spanned to the same position is kept.
+    fn rows_by_source(&self) -> Vec<Option<usize>> {
+        // Each row is keyed by its earliest source position, if any.
+        type Row = (Option<(usize, Position)>, Vec<usize>);
+        let mut rows: Vec<Row> = vec![(None, Vec::new())];
+        for (idx, chunk) in self.generated.iter().enumerate() {
+            let (key, row) = rows.last_mut().unwrap();
+            row.push(idx);
+            if let Some(range) = chunk.range.map(|idx| self.range_list[idx]) {
+                let pos = (range.source, range.start);
+                *key = Some(key.map_or(pos, |key| key.min(pos)));
+            }
+            if chunk.text == "\n" {
+                rows.push((None, Vec::new()));
+            }
         }
-        let range_idx = self.add_span(span);
-        match s {
-            "}" => {
-                self.indent -= 1;
-                if !self.newline {
-                    self.newline = true;
-                    self.add_str("\n", None);
-                }
+        let mut groups: Vec<Row> = Vec::new();
+        for (key, row) in rows {
+            match groups.last_mut() {
+                Some((_, group)) if key.is_none() => group.extend(row),
+                _ => groups.push((key, row)),
             }
-            _ => {}
         }
-        if self.newline {
-            self.newline = false;
-            for _ in 0..self.indent {
-                self.add_str("    ", None);
+        groups.sort_by_key(|(key, _)| *key);
+        let mut res: Vec<Option<usize>> = Vec::with_capacity(self.generated.len());
+        for (_, group) in groups {
+            // The last row doesn't necessarily end in a line break.
+            if let Some(&Some(idx)) = res.last() {
+                if self.generated[idx].text != "\n" {
+                    res.push(None);
+                }
             }
+            res.extend(group.into_iter().map(Some));
         }
-        self.add_str(s, range_idx);
-        match s {
-            "{" => {
-                self.indent += 1;
-                self.newline = true;
-                self.add_str("\n", None);
+        res
+    }
+    /// Adds a range of the file at `path`, returning its index.
+    ///
+    /// Sources are identified by their canonical path, so a file reached
+    /// through different paths is one source, and files that merely share a
+    /// relative path under different roots are different sources. Each path
+    /// seen is remembered, so the file system is only consulted once for it.
+    fn add_range(&mut self, path: PathBuf, start: Position, end: Position) -> usize {
+        let source = match self.sources.get(&path) {
+            Some(&source) => source,
+            None => {
+                let canonical = canonical_path(path.clone());
+                let source = match self.sources.get(&canonical) {
+                    Some(&source) => source,
+                    None => {
+                        let source = self.source_list.len();
+                        self.sources.insert(canonical.clone(), source);
+                        self.source_list.push(canonical);
+                        source
+                    }
+                };
+                self.sources.insert(path, source);
+                source
             }
-            ";" | "}" => {
-                self.newline = true;
-                self.add_str("\n", None);
+        };
+        let range = Range { source, start, end };
+        match self.ranges.get(&range) {
+            Some(&range_idx) => range_idx,
+            None => {
+                let range_idx = self.range_list.len();
+                self.ranges.insert(range, range_idx);
+                self.range_list.push(range);
+                range_idx
             }
-            _ => {}
-        }
-        if self.newline {
-            self.needs_space = NeedsSpace::Never;
-        }
-    }
-    fn visit_token_stream(&mut self, token_stream: TokenStream) {
-        for token_tree in token_stream {
-            self.visit_token_tree(token_tree);
-        }
-    }
-    fn visit_token_tree(&mut self, token_tree: TokenTree) {
-        match token_tree {
-            TokenTree::Group(group) => {
-                self.visit_str(
-                    match group.delimiter() {
-                        Delimiter::Parenthesis => "(",
-                        Delimiter::Brace => "{",
-                        Delimiter::Bracket => "[",
-                        Delimiter::None => "",
-                    },
-                    group.span_open(),
-                );
-                self.visit_token_stream(group.stream());
-                self.visit_str(
-                    match group.delimiter() {
-                        Delimiter::Parenthesis => ")",
-                        Delimiter::Brace => "}",
-                        Delimiter::Bracket => "]",
-                        Delimiter::None => "",
-                    },
-                    group.span_close(),
-                );
+        }
+    }
+}
+
+/// An error produced while generating a report.
+#[derive(Debug)]
+pub enum SpanrError {
+    /// None of the tokens carried a span pointing into a real source file.
+    ///
+    /// This happens when the `span-locations` feature is disabled, or when
+    /// the compiler does not provide span locations to procedural macros.
+    NoSpans,
+    /// The template is missing a required placeholder, such as `{LEFT}`.
+    MissingPlaceholder(&'static str),
+    /// Some of the source files couldn't be read. Each is given along with
+    /// the error encountered reading it.
+    UnreadableSources(Vec<(PathBuf, io::Error)>),
+    /// The code to render couldn't be split into tokens.
+    Lex(proc_macro2::LexError),
+    /// A source location wasn't of the form `path:line:column`.
+    InvalidLocation(String),
+    /// A color given to [`Spanr::palette`] contained `{`, `}`, `;` or `<`,
+    /// so would have broken out of the report's stylesheet.
+    InvalidColor(String),
+    /// Writing the report failed.
+    Io(io::Error),
+}
+
+impl Display for SpanrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpanrError::NoSpans => write!(
+                f,
+                "no tokens have spans pointing into a real source file \
+                 (is the `span-locations` feature enabled?)"
+            ),
+            SpanrError::MissingPlaceholder(name) => {
+                write!(f, "template is missing the `{{{}}}` placeholder", name)
             }
-            TokenTree::Ident(ident) => {
-                if self.needs_space != NeedsSpace::Never {
-                    self.add_str(" ", None);
+            SpanrError::UnreadableSources(errors) => {
+                write!(f, "failed to read source files:")?;
+                for (path, e) in errors {
+                    write!(f, " {}: {};", path.display(), e)?;
                 }
-                self.visit_str(&ident.to_string(), ident.span());
-                self.needs_space = NeedsSpace::IfNotPunct;
+                Ok(())
+            }
+            SpanrError::Lex(e) => write!(f, "failed to tokenize code: {}", e),
+            SpanrError::InvalidLocation(location) => write!(
+                f,
+                "invalid source location `{}`, expected `path:line:column`",
+                location
+            ),
+            SpanrError::InvalidColor(color) => write!(f, "invalid palette color `{}`", color),
+            SpanrError::Io(e) => write!(f, "failed to write report: {}", e),
+        }
+    }
+}
+
+impl Error for SpanrError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpanrError::NoSpans
+            | SpanrError::MissingPlaceholder(_)
+            | SpanrError::InvalidLocation(_)
+            | SpanrError::InvalidColor(_) => None,
+            SpanrError::UnreadableSources(errors) => {
+                errors.first().map(|(_, e)| e as &(dyn Error + 'static))
+            }
+            SpanrError::Lex(e) => Some(e),
+            SpanrError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for SpanrError {
+    fn from(other: io::Error) -> Self {
+        SpanrError::Io(other)
+    }
+}
+
+/// The span mapping computed for a token stream.
+///
+/// This is the same data that [`generate_html`] renders: the pretty-printed
+/// generated code split into chunks, the ranges of source code those chunks
+/// were spanned to, and the reconstructed fragments of those source files.
+#[derive(Debug, Clone)]
+pub struct SpanMap {
+    ranges: Ranges,
+    source_parts: SourceParts,
+}
+
+impl SpanMap {
+    /// Iterates over the source files referenced by this map, along with
+    /// their source index.
+    pub fn sources(&self) -> impl Iterator<Item = (usize, &Path)> {
+        self.ranges
+            .source_list
+            .iter()
+            .enumerate()
+            .map(|(source, path)| (source, path.as_path()))
+    }
+    /// Returns the path of the source file with the given index.
+    pub fn source_path(&self, source: usize) -> Option<&Path> {
+        self.ranges.source_list.get(source).map(PathBuf::as_path)
+    }
+    /// Iterates over the distinct source ranges, along with their range index.
+    pub fn ranges(&self) -> impl Iterator<Item = (usize, Range)> + '_ {
+        self.ranges.range_list.iter().copied().enumerate()
+    }
+    /// Returns the range with the given index.
+    pub fn range(&self, idx: usize) -> Option<Range> {
+        self.ranges.range_list.get(idx).copied()
+    }
+    /// Resolves a range index to the path and extent of the source it
+    /// refers to.
+    pub fn resolve(&self, idx: usize) -> Option<(PathBuf, Position, Position)> {
+        let range = self.range(idx)?;
+        let path = self.source_path(range.source)?;
+        Some((path.to_path_buf(), range.start, range.end))
+    }
+    /// Iterates over the chunks of pretty-printed generated code, along with
+    /// the index of the range each chunk was spanned to, if any.
+    pub fn generated(&self) -> impl Iterator<Item = (&str, Option<usize>)> {
+        self.ranges
+            .generated
+            .iter()
+            .map(|chunk| (chunk.text.as_str(), chunk.range))
+    }
+    /// Iterates over the chunks of pretty-printed generated code, classified
+    /// by whether they were written for a spanned token, a token without a
+    /// span, or by the pretty-printer.
+    pub fn chunks(&self) -> impl Iterator<Item = GeneratedChunk<'_>> {
+        self.ranges.generated.iter().map(|chunk| {
+            let text = chunk.text.as_str();
+            match chunk.range {
+                Some(idx) => GeneratedChunk::Spanned(text, idx),
+                None if chunk.layout => GeneratedChunk::Layout(text),
+                None => GeneratedChunk::Synthetic(text),
             }
-            TokenTree::Punct(punct) => {
-                if self.needs_space == NeedsSpace::Always {
-                    self.add_str(" ", None);
+        })
+    }
+    /// Finds the source range of the generated code at the given position.
+    ///
+    /// `line` is 1-based and `column` is 0-based, like [`Position`]. Returns
+    /// `None` if the position is outside the generated code, or falls within
+    /// a chunk that has no span, such as whitespace inserted by the
+    /// pretty-printer.
+    pub fn source_at_generated(
+        &self,
+        line: usize,
+        column: usize,
+    ) -> Option<(PathBuf, Position, Position)> {
+        let target = Position { line, column };
+        let mut pos = Position { line: 1, column: 0 };
+        for (text, idx) in self.generated() {
+            for c in text.chars() {
+                if pos == target {
+                    return self.resolve(idx?);
                 }
-                self.visit_str(&punct.to_string(), punct.span());
-                if punct.spacing() == Spacing::Alone {
-                    self.needs_space = NeedsSpace::Always;
+                if c == '\n' {
+                    pos.line += 1;
+                    pos.column = 0;
+                } else {
+                    pos.column += 1;
                 }
             }
-            TokenTree::Literal(literal) => {
-                if self.needs_space != NeedsSpace::Never {
-                    self.add_str(" ", None);
-                }
-                self.visit_str(&literal.to_string(), literal.span());
-                self.needs_space = NeedsSpace::IfNotPunct;
+            if pos > target {
+                return None;
+            }
+        }
+        None
+    }
+    /// Finds the tokens of generated code that have no span pointing into a
+    /// real source file, such as those given `Span::call_site()`.
+    ///
+    /// Returns the line and column each token starts at in the generated
+    /// code, counted as in [`SpanMap::source_at_generated`], along with its
+    /// text. Text laid out by the pretty-printer is left out, as described
+    /// in [`GeneratedChunk::Layout`].
+    pub fn synthetic_regions(&self) -> Vec<(usize, usize, String)> {
+        let mut res = Vec::new();
+        let mut pos = Position { line: 1, column: 0 };
+        for chunk in &self.ranges.generated {
+            if chunk.range.is_none() && !chunk.is_layout() {
+                res.push((pos.line, pos.column, chunk.text.clone()));
             }
+            pos = advance(pos, &chunk.text);
         }
+        res
+    }
+    /// Counts the sources, ranges and tokens of this map.
+    pub fn stats(&self) -> SpanStats {
+        self.ranges.stats()
+    }
+    /// Iterates over the chunks of reconstructed source code, along with the
+    /// indexes of all ranges covering each chunk.
+    ///
+    /// Zero-width ranges are given an empty chunk of their own, where they
+    /// start.
+    pub fn source_parts(&self) -> impl Iterator<Item = (&str, &[usize])> {
+        self.source_parts
+            .parts
+            .iter()
+            .map(|(text, indexes)| (text.as_str(), indexes.as_slice()))
     }
 }
 
-fn generate_ranges(token_stream: TokenStream) -> Ranges {
-    let mut res = TokenVisitor {
-        ranges: Default::default(),
-        indent: 0,
-        newline: true,
-        needs_space: NeedsSpace::Never,
-    };
-    res.visit_token_stream(token_stream);
-    res.ranges
+/// A chunk of pretty-printed generated code, as returned by
+/// [`SpanMap::chunks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneratedChunk<'a> {
+    /// Text written for a token spanned to the range with the given index.
+    Spanned(&'a str, usize),
+    /// Text written for a token without a span pointing into a real source
+    /// file.
+    Synthetic(&'a str),
+    /// Text inserted by the pretty-printer, such as whitespace, indentation
+    /// and the labels of sections and sources.
+    Layout(&'a str),
 }
 
-struct LoadedSource {
-    path: PathBuf,
-    text: Vec<String>,
-    range_seps: Vec<RangeSeparator>,
+impl<'a> GeneratedChunk<'a> {
+    /// Returns the text of the chunk.
+    pub fn text(self) -> &'a str {
+        match self {
+            GeneratedChunk::Spanned(text, _)
+            | GeneratedChunk::Synthetic(text)
+            | GeneratedChunk::Layout(text) => text,
+        }
+    }
 }
 
-struct SourceParts {
-    parts: Vec<(String, Vec<usize>)>,
+/// Counts summarizing the span mapping of a token stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpanStats {
+    /// The number of source files referenced.
+    pub sources: usize,
+    /// The number of distinct source ranges tokens were spanned to.
+    pub ranges: usize,
+    /// The number of chunks of generated code written for tokens, ignoring
+    /// the whitespace laid out between them.
+    pub tokens: usize,
+    /// The number of those chunks without a span pointing into a real
+    /// source file.
+    pub unspanned_tokens: usize,
 }
 
-impl SourceParts {
-    fn add_unspanned(&mut self, s: &str) {
-        self.parts.push((s.into(), Vec::new()));
+impl Display for SpanStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} sources, {} ranges, {} tokens ({} unspanned)",
+            self.sources, self.ranges, self.tokens, self.unspanned_tokens
+        )
     }
-    fn add(&mut self, s: String, indexes: &HashSet<usize>) {
-        self.parts.push((s, indexes.iter().copied().collect()));
+}
+
+impl Ranges {
+    fn stats(&self) -> SpanStats {
+        let tokens = self.generated.iter().filter(|chunk| !chunk.is_layout());
+        SpanStats {
+            sources: self.source_list.len(),
+            ranges: self.range_list.len(),
+            tokens: tokens.clone().count(),
+            unspanned_tokens: tokens.filter(|chunk| chunk.range.is_none()).count(),
+        }
     }
 }
 
-fn load_original_source(ranges: &Ranges) -> SourceParts {
-    let mut loaded_sources: HashMap<_, _> = ranges
-        .sources
-        .iter()
-        .filter_map(|(path, &source)| {
-            let text = fs::read_to_string(path).ok()?;
-            Some((
-                source,
-                LoadedSource {
-                    path: path.clone(),
-                    text: text.lines().map(Into::into).collect(),
-                    range_seps: Vec::new(),
-                },
-            ))
-        })
-        .collect();
-
-    for (&range, &idx) in &ranges.ranges {
-        if let Some(loaded_source) = loaded_sources.get_mut(&range.source) {
-            loaded_source.range_seps.push(RangeSeparator {
-                pos: range.start,
-                sep: SeparatorType::Start,
-                idx,
-            });
-            loaded_source.range_seps.push(RangeSeparator {
-                pos: range.end,
-                sep: SeparatorType::End,
-                idx,
-            });
+/// The color scheme a report is first shown with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Dark text on a light background.
+    #[default]
+    Light,
+    /// Light text on a dark background.
+    Dark,
+}
+
+impl Theme {
+    /// The value of the `data-theme` attribute selecting this theme.
+    fn name(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
         }
     }
+}
 
-    let mut source_parts = SourceParts { parts: Vec::new() };
-    for loaded_source in loaded_sources.values_mut() {
-        loaded_source.range_seps.sort();
+/// The parts of an HTML report, for embedding in a page of your own.
+///
+/// The panels must both be added to the page before the script is run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlFragment {
+    /// The panel of generated code, as a `<div id="left">` element.
+    pub left: String,
+    /// The panel of source code, as a `<div id="right">` element.
+    pub right: String,
+    /// The CSS of the default template, followed by the rules coloring each
+    /// range.
+    pub style: String,
+    /// The JavaScript highlighting the ranges under the cursor.
+    pub script: String,
+    /// The classes enabling optional features, to be given to an element
+    /// containing both panels.
+    pub features: String,
+}
 
-        source_parts.add_unspanned("\n");
-        source_parts.add_unspanned("//======================================");
-        source_parts.add_unspanned("\n");
-        source_parts.add_unspanned("// ");
-        source_parts.add_unspanned(&loaded_source.path.display().to_string());
-        source_parts.add_unspanned("\n");
-        source_parts.add_unspanned("//======================================");
-        source_parts.add_unspanned("\n");
+/// How the panels of a report are arranged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// The generated code and the source code side by side.
+    #[default]
+    SideBySide,
+    /// The generated code above the source code, for narrow screens.
+    Stacked,
+}
 
-        let mut indexes = HashSet::new();
-        let mut pos = loaded_source.range_seps.first().unwrap().pos;
-        pos.column = 0;
+/// The kinds of token whose spans a report can be restricted to, with
+/// [`Spanr::only_token_kinds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// The delimiters of a group, such as parentheses or braces.
+    Group,
+    /// An identifier or keyword.
+    Ident,
+    /// A literal, including the text of a doc comment.
+    Literal,
+    /// A punctuation character.
+    Punct,
+}
 
-        for range_sep in &loaded_source.range_seps {
-            while pos.line < range_sep.pos.line {
-                if let Some(line_text) = loaded_source.text.get(pos.line.wrapping_sub(1)) {
-                    let s: String = line_text.chars().skip(pos.column).collect();
-                    source_parts.add(s, &indexes);
-                }
-                source_parts.add_unspanned("\n");
-                pos.line += 1;
-                pos.column = 0;
+/// A configurable report generator.
+///
+/// ```no_run
+/// # let token_stream = proc_macro2::TokenStream::new();
+/// spanr::Spanr::new()
+///     .indent_unit("  ")
+///     .save(token_stream, "out.html")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Spanr {
+    indent_unit: String,
+    template: Cow<'static, str>,
+    line_numbers: bool,
+    indent_padding: bool,
+    context_lines: usize,
+    parsed_source: Option<(PathBuf, String)>,
+    syntax_highlight: bool,
+    original_literals: bool,
+    preserve_formatting: bool,
+    coverage: bool,
+    max_width: Option<usize>,
+    max_indent: Option<usize>,
+    source_cache: Option<SourceCache>,
+    source_provider: Option<SharedSourceProvider>,
+    source_overrides: HashMap<PathBuf, String>,
+    group_by_source: bool,
+    full_source: bool,
+    hashed_classes: bool,
+    split_literals: bool,
+    search: bool,
+    line_highlight: bool,
+    theme: Theme,
+    layout: Layout,
+    footer: bool,
+    timestamp: bool,
+    palette: Option<Vec<String>>,
+    labels: HashMap<(PathBuf, Position, Position), String>,
+    focus: Vec<(PathBuf, Position, Position)>,
+    #[cfg(feature = "prettyplease")]
+    prettyplease: bool,
+    only_sources: Option<Vec<PathBuf>>,
+    only_token_kinds: Option<Vec<TokenKind>>,
+    source_root: Option<PathBuf>,
+    relative_to: Option<PathBuf>,
+    break_where_clauses: bool,
+    minify: bool,
+    sort_by_source_position: bool,
+    source_header: Cow<'static, str>,
+    tab_width: Option<usize>,
+}
+
+impl Default for Spanr {
+    fn default() -> Self {
+        Self {
+            indent_unit: "    ".into(),
+            template: TEMPLATE_HTML.into(),
+            line_numbers: false,
+            indent_padding: false,
+            context_lines: 0,
+            parsed_source: None,
+            syntax_highlight: false,
+            original_literals: false,
+            preserve_formatting: false,
+            coverage: false,
+            max_width: None,
+            max_indent: None,
+            source_cache: None,
+            source_provider: None,
+            source_overrides: HashMap::new(),
+            group_by_source: false,
+            full_source: false,
+            hashed_classes: false,
+            split_literals: false,
+            search: false,
+            line_highlight: true,
+            theme: Theme::Light,
+            layout: Layout::SideBySide,
+            footer: true,
+            timestamp: false,
+            palette: None,
+            labels: HashMap::new(),
+            focus: Vec::new(),
+            #[cfg(feature = "prettyplease")]
+            prettyplease: false,
+            only_sources: None,
+            only_token_kinds: None,
+            source_root: None,
+            relative_to: None,
+            break_where_clauses: false,
+            minify: false,
+            sort_by_source_position: false,
+            source_header: SOURCE_HEADER.into(),
+            tab_width: None,
+        }
+    }
+}
+
+impl Spanr {
+    /// Creates a report generator with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the string emitted for each level of indentation in the
+    /// generated code. Defaults to four spaces.
+    pub fn indent_unit(mut self, indent_unit: impl Into<String>) -> Self {
+        self.indent_unit = indent_unit.into();
+        self
+    }
+    /// Sets the HTML template used for reports.
+    ///
+    /// The `{LEFT}` and `{RIGHT}` placeholders are required, and are replaced
+    /// with the generated and source panels. The optional `{STYLE}`
+    /// placeholder is replaced with the CSS of the default template,
+    /// `{SEARCH}` with the search box, if enabled, `{FEATURES}` with the
+    /// classes enabling optional features and layouts, `{THEME}` with the
+    /// name of the initial theme, `{PALETTE}` with a `<style>` element
+    /// coloring the ranges, `{FOOTER}` with the footer, if enabled, and
+    /// `{SCRIPT}` with the JavaScript of the default template.
+    ///
+    /// The default template inlines all of its CSS and JavaScript, so that a
+    /// report is a single file that works wherever it is opened. A custom
+    /// template can keep that by using the `{STYLE}` and `{SCRIPT}`
+    /// placeholders rather than linking to the assets.
+    pub fn template(mut self, template: impl Into<Cow<'static, str>>) -> Self {
+        self.template = template.into();
+        self
+    }
+    /// Sets the header written above each source file in the source panel,
+    /// in which `{path}` is replaced with the path of the file.
+    ///
+    /// The header may span several lines, and an empty header leaves it out
+    /// entirely. Defaults to the path between two rules of `=`s.
+    pub fn source_header(mut self, source_header: impl Into<Cow<'static, str>>) -> Self {
+        self.source_header = source_header.into();
+        self
+    }
+    /// Sets whether each row of both panels is prefixed with its line number.
+    /// Defaults to `false`.
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.line_numbers = line_numbers;
+        self
+    }
+    /// Sets whether the indentation of each row of the generated panel is
+    /// written as padding on the row, rather than as whitespace.
+    ///
+    /// This keeps the indentation out of text selections, and leaves fewer
+    /// elements in the page. Defaults to `false`.
+    pub fn indent_padding(mut self, indent_padding: bool) -> Self {
+        self.indent_padding = indent_padding;
+        self
+    }
+    /// Sets the number of lines of unspanned context shown before the first
+    /// and after the last range of each source file. Defaults to `0`.
+    pub fn context_lines(mut self, context_lines: usize) -> Self {
+        self.context_lines = context_lines;
+        self
+    }
+    /// Sets whether each source file is shown in full, rather than only the
+    /// lines from its first range to its last. Overrides
+    /// [`Spanr::context_lines`]. Defaults to `false`.
+    pub fn full_source(mut self, full_source: bool) -> Self {
+        self.full_source = full_source;
+        self
+    }
+    /// Sets whether the class of each range is derived from a hash of its
+    /// source path and extent, rather than from its index.
+    ///
+    /// Hashed classes, such as `c6f2a9e03d1b4c58`, stay the same across runs
+    /// and across reports of different token streams, so can be targeted by
+    /// custom CSS or compared between reports. Defaults to `false`.
+    pub fn hashed_classes(mut self, hashed_classes: bool) -> Self {
+        self.hashed_classes = hashed_classes;
+        self
+    }
+    /// Sets whether the HTML report is written more compactly, by leaving out
+    /// the elements around whitespace outside of any range.
+    ///
+    /// This makes large reports smaller and faster to display, without
+    /// changing how they look or behave. Defaults to `false`.
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+    /// Sets whether the rows of the generated code panel are shown in order
+    /// of the earliest source position they were spanned to, rather than in
+    /// the order they were generated.
+    ///
+    /// Rows without any spanned tokens stay after the row before them. Only
+    /// the display is affected, and blocks can't be folded while it is
+    /// reordered. Defaults to `false`.
+    pub fn sort_by_source_position(mut self, sort_by_source_position: bool) -> Self {
+        self.sort_by_source_position = sort_by_source_position;
+        self
+    }
+    /// Sets whether the report has a search box, which highlights matching
+    /// text in both panels, along with every occurrence of the ranges that
+    /// text is spanned to. Defaults to `false`.
+    ///
+    /// The search box replaces the optional `{SEARCH}` placeholder of the
+    /// template.
+    pub fn search(mut self, search: bool) -> Self {
+        self.search = search;
+        self
+    }
+    /// Sets whether the row of generated code under the cursor is
+    /// highlighted, along with the source rows of the ranges it is spanned
+    /// to. Defaults to `true`.
+    ///
+    /// This is enabled through the `{FEATURES}` placeholder of the
+    /// template.
+    pub fn line_highlight(mut self, line_highlight: bool) -> Self {
+        self.line_highlight = line_highlight;
+        self
+    }
+    /// Sets the theme the report is first shown with. Defaults to
+    /// [`Theme::Light`].
+    ///
+    /// The report has a button to toggle the theme, and remembers the
+    /// reader's choice in preference to this one. The theme is written
+    /// through the `{THEME}` placeholder of the template.
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+    /// Sets how the panels of the report are arranged. Defaults to
+    /// [`Layout::SideBySide`].
+    ///
+    /// The layout is selected through the `{FEATURES}` placeholder of the
+    /// template.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+    /// Sets whether the report has a footer naming the version of spanr that
+    /// generated it. Defaults to `true`.
+    ///
+    /// The footer replaces the optional `{FOOTER}` placeholder of the
+    /// template.
+    pub fn footer(mut self, footer: bool) -> Self {
+        self.footer = footer;
+        self
+    }
+    /// Sets whether the footer also gives the time the report was generated.
+    /// Defaults to `false`, so that reports of the same code are identical.
+    pub fn timestamp(mut self, timestamp: bool) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+    /// Sets the CSS colors given to the backgrounds of ranges, in place of
+    /// the hues derived from their source files.
+    ///
+    /// Ranges take the colors in turn, wrapping around to the first once
+    /// they run out, so a small colorblind-safe palette can still be used.
+    /// An empty palette restores the default coloring. Colors containing
+    /// `{`, `}`, `;` or `<` can't be written into the stylesheet, so
+    /// building a report with them fails with [`SpanrError::InvalidColor`].
+    pub fn palette<S: Into<String>>(mut self, colors: impl IntoIterator<Item = S>) -> Self {
+        let colors: Vec<_> = colors.into_iter().map(Into::into).collect();
+        self.palette = (!colors.is_empty()).then_some(colors);
+        self
+    }
+    /// Sets whether the generated code is formatted by prettyplease, as
+    /// rustfmt would format it, rather than by the built-in pretty-printer.
+    /// Defaults to `false`.
+    ///
+    /// This only applies to token streams that parse as a whole file, and
+    /// any other falls back to the built-in pretty-printer. Tokens added by
+    /// prettyplease, such as trailing commas, are left without a span, and
+    /// the layout options of the built-in pretty-printer have no effect.
+    #[cfg(feature = "prettyplease")]
+    pub fn prettyplease(mut self, prettyplease: bool) -> Self {
+        self.prettyplease = prettyplease;
+        self
+    }
+    /// Attaches a label to the range of a source file from `start` to `end`,
+    /// shown as a tooltip on the code spanned to it in both panels.
+    ///
+    /// The label only applies to tokens spanned to exactly that range.
+    /// Labeling a range again replaces its label.
+    pub fn label(
+        mut self,
+        path: impl Into<PathBuf>,
+        start: Position,
+        end: Position,
+        label: impl Into<String>,
+    ) -> Self {
+        self.labels
+            .insert((canonical_path(path.into()), start, end), label.into());
+        self
+    }
+    /// Returns the label attached to range `idx`, if any.
+    fn label_of(&self, ranges: &Ranges, idx: usize) -> Option<&str> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        let range = ranges.range_list[idx];
+        let path = ranges.source_list[range.source].clone();
+        self.labels
+            .get(&(path, range.start, range.end))
+            .map(String::as_str)
+    }
+    /// Draws attention to the region of a source file from `start` to `end`,
+    /// or the position `start` if they are the same.
+    ///
+    /// Code spanned to any range overlapping the region is given the `focus`
+    /// CSS class in both panels, and the first of it in each panel is
+    /// scrolled into view when the report is opened.
+    pub fn focus(mut self, path: impl Into<PathBuf>, start: Position, end: Position) -> Self {
+        self.focus.push((canonical_path(path.into()), start, end));
+        self
+    }
+    /// Draws attention to a position given as `path:line:column`, as in
+    /// compiler diagnostics, where both the line and column are 1-based.
+    ///
+    /// Fails with [`SpanrError::InvalidLocation`] if the location isn't of
+    /// that form.
+    pub fn focus_location(self, location: &str) -> Result<Self, SpanrError> {
+        let invalid = || SpanrError::InvalidLocation(location.into());
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next().and_then(|column| column.parse::<usize>().ok());
+        let line = parts.next().and_then(|line| line.parse::<usize>().ok());
+        let (path, line, column) = match (parts.next(), line, column) {
+            (Some(path), Some(line), Some(column))
+                if !path.is_empty() && line > 0 && column > 0 =>
+            {
+                (path, line, column - 1)
             }
-            if pos.column < range_sep.pos.column {
-                if let Some(line_text) = loaded_source.text.get(pos.line.wrapping_sub(1)) {
-                    let s: String = line_text
-                        .chars()
-                        .skip(pos.column)
-                        .take(range_sep.pos.column - pos.column)
-                        .collect();
-                    source_parts.add(s, &indexes);
+            _ => return Err(invalid()),
+        };
+        let pos = Position { line, column };
+        Ok(self.focus(path, pos, pos))
+    }
+    /// Returns whether range `idx` overlaps any of the focused regions.
+    fn is_focused(&self, ranges: &Ranges, idx: usize) -> bool {
+        let range = ranges.range_list[idx];
+        let path = &ranges.source_list[range.source];
+        self.focus.iter().any(|(focus_path, start, end)| {
+            focus_path == path
+                && if start == end {
+                    range.start <= *start && (*start < range.end || range.start == range.end)
+                } else {
+                    range.start < *end && *start < range.end
                 }
-                pos.column = range_sep.pos.column;
+        })
+    }
+    /// Restricts the report to ranges of the given source files.
+    ///
+    /// Tokens spanned to any other file are written as if they had no span,
+    /// and those files are left out of the source panel. Paths are compared
+    /// once made canonical, so a relative path matches spans into the file
+    /// it names from the current directory.
+    pub fn only_sources<P: Into<PathBuf>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.only_sources = Some(
+            paths
+                .into_iter()
+                .map(|path| canonical_path(path.into()))
+                .collect(),
+        );
+        self
+    }
+    /// Restricts the report to the spans of tokens of the given kinds, such as
+    /// only identifiers.
+    ///
+    /// Tokens of any other kind are still written, but as if they had no span.
+    pub fn only_token_kinds(mut self, kinds: impl IntoIterator<Item = TokenKind>) -> Self {
+        self.only_token_kinds = Some(kinds.into_iter().collect());
+        self
+    }
+    /// Restricts the report to ranges of files within the directory `root`,
+    /// such as the `CARGO_MANIFEST_DIR` of a crate.
+    ///
+    /// Tokens spanned to files elsewhere, such as the source of a dependency,
+    /// are written as if they had no span, and the source panel warns about
+    /// each file left out. Paths are compared once made canonical.
+    pub fn source_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.source_root = Some(canonical_path(root.into()));
+        self
+    }
+    /// Shows the paths of source files relative to the current directory,
+    /// rather than as the full paths the spans report, if they are within it.
+    ///
+    /// Only the paths shown in the report are affected, and sources are still
+    /// told apart by their canonical paths.
+    pub fn relative_paths(mut self, relative_paths: bool) -> Self {
+        self.relative_to = match relative_paths {
+            true => std::env::current_dir().ok().map(canonical_path),
+            false => None,
+        };
+        self
+    }
+    /// Shows the paths of source files relative to the directory `base`, such
+    /// as the root of a repository, if they are within it.
+    pub fn relative_paths_to(mut self, base: impl Into<PathBuf>) -> Self {
+        self.relative_to = Some(canonical_path(base.into()));
+        self
+    }
+    /// Returns how `path` is shown in the report.
+    fn display_path(&self, path: &Path) -> String {
+        self.relative_to
+            .as_ref()
+            .and_then(|base| path.strip_prefix(base).ok())
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+    /// Expands tabs in the source panel to spaces, aligned to multiples of
+    /// `tab_width` columns.
+    ///
+    /// Tabs are otherwise left for the browser to display, which can make
+    /// ranges starting after them hard to line up by eye.
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width);
+        self
+    }
+    /// Attributes the spans of token streams parsed from a string at runtime
+    /// to the file `path`, whose contents are `text`.
+    ///
+    /// Such spans don't point into a file on disk, so are otherwise treated as
+    /// having no span at all.
+    pub fn parsed_source(mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        self.parsed_source = Some((canonical_path(path.into()), text.into()));
+        self
+    }
+    /// Sets whether keywords, literals and lifetimes in the generated panel
+    /// are tagged with the `kw`, `lit-str`, `lit-num` and `lifetime` CSS
+    /// classes, so that they can be colored. Defaults to `false`.
+    pub fn syntax_highlight(mut self, syntax_highlight: bool) -> Self {
+        self.syntax_highlight = syntax_highlight;
+        self
+    }
+    /// Sets whether literals in the generated panel are written as the source
+    /// text their span points at, rather than as proc-macro2 renders them,
+    /// which may differ in escaping or formatting. Literals whose source
+    /// can't be read are rendered as usual. Defaults to `false`.
+    pub fn original_literals(mut self, original_literals: bool) -> Self {
+        self.original_literals = original_literals;
+        self
+    }
+    /// Sets whether string and character literals are split into their
+    /// opening quote, contents and closing quote, each spanned to its own
+    /// part of the literal in the source, rather than spanned as a whole.
+    ///
+    /// Prefixes such as `b` or `r#` are kept with the opening quote, and
+    /// suffixes with the closing one. Literals that don't line up with their
+    /// source, such as those written across several lines, are spanned as a
+    /// whole. Defaults to `false`.
+    pub fn split_literals(mut self, split_literals: bool) -> Self {
+        self.split_literals = split_literals;
+        self
+    }
+    /// Sets whether the generated panel keeps the formatting of the source
+    /// code where it can, rather than being pretty-printed throughout.
+    ///
+    /// Tokens spanned to consecutive places in a source file are separated
+    /// by the whitespace and comments between them in that file, and
+    /// literals are written as in [`Spanr::original_literals`]. Tokens
+    /// without a real span are still spaced by the pretty-printer. Defaults
+    /// to `false`.
+    pub fn preserve_formatting(mut self, preserve_formatting: bool) -> Self {
+        self.preserve_formatting = preserve_formatting;
+        self
+    }
+    /// Sets whether source text between ranges that isn't covered by any of
+    /// them is tagged with the `unused` CSS class, to reveal input that was
+    /// dropped from the generated code. Defaults to `false`.
+    pub fn coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage;
+        self
+    }
+    /// Sets whether `where` clauses are written as rustfmt lays them out,
+    /// with `where` on a line of its own followed by each bound on its own
+    /// indented line. Defaults to `false`.
+    pub fn break_where_clauses(mut self, break_where_clauses: bool) -> Self {
+        self.break_where_clauses = break_where_clauses;
+        self
+    }
+    /// Sets the width past which lines of generated code are broken after a
+    /// comma, continuing one level further indented.
+    ///
+    /// Lines are only broken after `{`, `}` and `;` by default.
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+    /// Sets the most levels of indentation written before a line of generated
+    /// code, however deeply it is nested.
+    ///
+    /// Lines nested more deeply are written at this level, which keeps
+    /// deeply nested code readable on narrow displays. Indentation is
+    /// unlimited by default.
+    pub fn max_indent(mut self, max_indent: usize) -> Self {
+        self.max_indent = Some(max_indent);
+        self
+    }
+    /// Reads source files through `cache`, so that files referenced by many
+    /// reports are only read once.
+    pub fn source_cache(mut self, cache: SourceCache) -> Self {
+        self.source_cache = Some(cache);
+        self
+    }
+    /// Reads source files through `provider` rather than from the file
+    /// system, such as from memory.
+    ///
+    /// Files the provider doesn't have are shown as unavailable. The text
+    /// given by [`Spanr::parsed_source`] or [`Spanr::source_override`] is
+    /// still preferred, and the provider replaces any [`Spanr::source_cache`].
+    pub fn source_provider(mut self, provider: impl SourceProvider + 'static) -> Self {
+        self.source_provider = Some(SharedSourceProvider(Arc::new(provider)));
+        self
+    }
+    /// Uses `text` as the contents of the source file `path`, instead of
+    /// reading it from disk.
+    ///
+    /// This allows reports to show sources that don't exist on disk, such as
+    /// generated or virtual files.
+    pub fn source_override(mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        self.source_overrides
+            .insert(canonical_path(path.into()), text.into());
+        self
+    }
+    /// Uses the given texts as the contents of their source files, as with
+    /// [`Spanr::source_override`].
+    pub fn source_overrides(
+        mut self,
+        overrides: impl IntoIterator<Item = (PathBuf, String)>,
+    ) -> Self {
+        self.source_overrides.extend(
+            overrides
+                .into_iter()
+                .map(|(path, text)| (canonical_path(path), text)),
+        );
+        self
+    }
+    /// Sets whether a line labeling the source file is written to the
+    /// generated panel wherever consecutive spanned tokens switch between
+    /// source files. Defaults to `false`.
+    pub fn group_by_source(mut self, group_by_source: bool) -> Self {
+        self.group_by_source = group_by_source;
+        self
+    }
+    /// Walks a token stream and computes its span mapping, loading the
+    /// referenced source files from disk.
+    pub fn build_span_map(&self, token_stream: TokenStream) -> SpanMap {
+        let ranges = generate_ranges(token_stream, self);
+        let (source_parts, _) = load_original_source(&ranges, self);
+        SpanMap {
+            ranges,
+            source_parts,
+        }
+    }
+    /// Pairs the text of each token of the pretty-printed generated code
+    /// with the source text its span points at.
+    ///
+    /// The source text is `None` for tokens without a span, and for those
+    /// whose source file can't be read. Text laid out by the pretty-printer
+    /// is left out.
+    pub fn mapped_pairs(&self, token_stream: TokenStream) -> Vec<(String, Option<String>)> {
+        let ranges = generate_ranges(token_stream, self);
+        let mut sources = HashMap::new();
+        ranges
+            .generated
+            .iter()
+            .filter(|chunk| !chunk.is_layout())
+            .map(|chunk| {
+                let source_text = chunk.range.and_then(|idx| {
+                    let range = ranges.range_list[idx];
+                    let lines = sources
+                        .entry(range.source)
+                        .or_insert_with(|| {
+                            read_source(&ranges.source_list[range.source], self).ok()
+                        })
+                        .as_ref()?;
+                    slice_range(lines, range.start, range.end)
+                });
+                (chunk.text.clone(), source_text)
+            })
+            .collect()
+    }
+    /// Pretty-prints a token stream as it appears in the generated panel of
+    /// a report, ignoring spans.
+    ///
+    /// Source files are only read if a setting such as
+    /// [`Spanr::original_literals`] requires them.
+    pub fn pretty_print(&self, token_stream: TokenStream) -> String {
+        generate_ranges(token_stream, self)
+            .generated
+            .into_iter()
+            .map(|chunk| chunk.text)
+            .collect()
+    }
+    /// Counts the sources, ranges and tokens of the span mapping of a token
+    /// stream, without reading any source files.
+    pub fn stats(&self, token_stream: TokenStream) -> SpanStats {
+        generate_ranges(token_stream, self).stats()
+    }
+    /// Lists the source files the tokens of a token stream are spanned to,
+    /// in order of their path, without reading any of them.
+    pub fn referenced_sources(&self, token_stream: TokenStream) -> Vec<PathBuf> {
+        generate_ranges(token_stream, self).source_list
+    }
+    /// Finds the source text between ranges of a token stream that isn't
+    /// covered by any of them, ignoring whitespace and comments.
+    ///
+    /// Returns the path and extent of each gap, in order. Sources that can't
+    /// be read are skipped.
+    pub fn uncovered_spans(&self, token_stream: TokenStream) -> Vec<(PathBuf, Position, Position)> {
+        uncovered_spans_of(&generate_ranges(token_stream, self), self)
+    }
+    /// Finds the ranges of a token stream that look like they were given to
+    /// tokens by mistake, such as the span of the macro call, which is what
+    /// `Span::call_site()` returns.
+    ///
+    /// A range is suspicious if it is on one line, and several tokens with
+    /// different text are spanned to it. Returns the path and extent of each
+    /// suspicious range, in order, along with how many tokens are spanned to
+    /// it. No source files are read.
+    pub fn suspicious_spans(
+        &self,
+        token_stream: TokenStream,
+    ) -> Vec<(PathBuf, Position, Position, usize)> {
+        suspicious_spans_of(&generate_ranges(token_stream, self))
+    }
+    /// Renders the span mapping of a token stream as an HTML document.
+    ///
+    /// Returns [`SpanrError::NoSpans`] if none of the tokens could be mapped
+    /// back to a real source file, rather than producing an empty source
+    /// panel. Source files that can't be read are listed in a warning at the
+    /// top of the source panel.
+    pub fn build_html(&self, token_stream: TokenStream) -> Result<String, SpanrError> {
+        let mut res = Vec::new();
+        self.write_html(token_stream, &mut res)?;
+        Ok(String::from_utf8(res).expect("HTML output is always valid UTF-8"))
+    }
+    /// Renders the span mapping of a token stream as an HTML document,
+    /// streaming it to `out` as it is produced.
+    pub fn write_html<W: Write>(
+        &self,
+        token_stream: TokenStream,
+        out: W,
+    ) -> Result<(), SpanrError> {
+        let ranges = generate_ranges(token_stream, self);
+        self.write_report(ranges, |_, chunk| self.highlight_class(chunk), false, out)
+    }
+    /// Renders the span mapping of a token stream as an HTML document, like
+    /// [`Spanr::build_html`], but fails with
+    /// [`SpanrError::UnreadableSources`] if any source file can't be read.
+    pub fn try_build_html(&self, token_stream: TokenStream) -> Result<String, SpanrError> {
+        let ranges = generate_ranges(token_stream, self);
+        let mut res = Vec::new();
+        self.write_report(
+            ranges,
+            |_, chunk| self.highlight_class(chunk),
+            true,
+            &mut res,
+        )?;
+        Ok(String::from_utf8(res).expect("HTML output is always valid UTF-8"))
+    }
+    /// Renders the span mapping of several token streams as one HTML
+    /// document.
+    ///
+    /// Each token stream is written as a section of the generated panel,
+    /// headed by its label, while the source panel is shared between them.
+    pub fn build_html_multi(
+        &self,
+        streams: impl IntoIterator<Item = (String, TokenStream)>,
+    ) -> Result<String, SpanrError> {
+        let mut res = Vec::new();
+        self.write_html_multi(streams, &mut res)?;
+        Ok(String::from_utf8(res).expect("HTML output is always valid UTF-8"))
+    }
+    /// Renders the span mapping of several token streams as one HTML
+    /// document, streaming it to `out` as it is produced.
+    pub fn write_html_multi<W: Write>(
+        &self,
+        streams: impl IntoIterator<Item = (String, TokenStream)>,
+        out: W,
+    ) -> Result<(), SpanrError> {
+        let ranges = generate_ranges_multi(streams, self);
+        self.write_report(ranges, |_, chunk| self.highlight_class(chunk), false, out)
+    }
+    /// Returns the syntax highlighting class of a chunk of generated code, if
+    /// enabled.
+    fn highlight_class(&self, chunk: &Chunk) -> Option<&'static str> {
+        chunk
+            .highlight
+            .filter(|_| self.syntax_highlight)
+            .map(Highlight::class)
+    }
+    /// Returns the classes enabling optional features and layouts, separated
+    /// by spaces.
+    fn features(&self) -> String {
+        let features = [
+            self.line_highlight.then_some("line-highlight"),
+            (self.layout == Layout::Stacked).then_some("stacked"),
+        ];
+        let features: Vec<_> = features.iter().flatten().copied().collect();
+        features.join(" ")
+    }
+    /// Writes the rows of the generated code panel, tagging each chunk with
+    /// the CSS class returned by `chunk_class` for it and its index.
+    fn write_left(
+        &self,
+        out: &mut impl Write,
+        ranges: &Ranges,
+        chunk_class: impl Fn(usize, &Chunk) -> Option<&'static str>,
+    ) -> io::Result<()> {
+        let order = match self.sort_by_source_position {
+            true => ranges.rows_by_source(),
+            false => (0..ranges.generated.len()).map(Some).collect(),
+        };
+        let parts = order.into_iter().map(|idx| match idx {
+            Some(idx) => {
+                let chunk = &ranges.generated[idx];
+                let class = chunk_class(idx, chunk);
+                // Blocks can't be folded once their rows are reordered.
+                let block = chunk.block.filter(|_| !self.sort_by_source_position);
+                (chunk.text.as_str(), chunk.range, class, block)
             }
-            match range_sep.sep {
-                SeparatorType::Start => {
-                    indexes.insert(range_sep.idx);
-                }
-                SeparatorType::End => {
-                    indexes.remove(&range_sep.idx);
-                }
+            None => ("\n", None, None, None),
+        });
+        write_html_parts(out, self, ranges, parts, true, &mut 1)
+    }
+    /// Writes the rows of the source code panel, preceded by a warning for
+    /// each source file that couldn't be read or was outside the source root.
+    fn write_right(
+        &self,
+        out: &mut impl Write,
+        ranges: &Ranges,
+        source_parts: &SourceParts,
+        errors: &[(PathBuf, io::Error)],
+    ) -> io::Result<()> {
+        for (path, e) in errors {
+            write!(
+                out,
+                "<div class=\"warning\">warning: failed to read {}: {}</div>",
+                encode_text(&self.display_path(path)),
+                encode_text(&e.to_string())
+            )?;
+        }
+        for path in &ranges.outside_root {
+            write!(
+                out,
+                "<div class=\"warning\">warning: left out spans into {}, which is outside of \
+                 the source root</div>",
+                encode_text(&self.display_path(path))
+            )?;
+        }
+        write_nested_parts(
+            out,
+            self,
+            ranges,
+            source_parts
+                .parts
+                .iter()
+                .enumerate()
+                .map(|(idx, (text, indexes))| {
+                    let class = if source_parts.markers.contains(&idx) {
+                        Some("zero-width")
+                    } else if self.coverage && source_parts.unused.contains(&idx) {
+                        Some("unused")
+                    } else {
+                        None
+                    };
+                    (text.as_str(), indexes.as_slice(), class)
+                }),
+        )
+    }
+    /// Checks that the colors of the palette can be written into a stylesheet.
+    fn check_palette(&self) -> Result<(), SpanrError> {
+        let invalid = |color: &&String| color.contains(['{', '}', ';', '<']);
+        match self.palette.iter().flatten().find(invalid) {
+            Some(color) => Err(SpanrError::InvalidColor(color.clone())),
+            None => Ok(()),
+        }
+    }
+    /// Writes the CSS rules giving each range its background color.
+    fn write_range_colors(&self, out: &mut impl Write, ranges: &Ranges) -> io::Result<()> {
+        for (idx, (hue, step)) in ranges.range_colors().into_iter().enumerate() {
+            out.write_all(b".")?;
+            write_range_class(out, self, ranges, idx)?;
+            match &self.palette {
+                Some(palette) => write!(
+                    out,
+                    " {{ background-color: {}; }}",
+                    palette[idx % palette.len()]
+                )?,
+                // The base lightness depends on the theme.
+                None => write!(
+                    out,
+                    " {{ background-color: hsl({}, 90%, \
+                     calc(var(--range-lightness) - {}%)); }}",
+                    hue,
+                    step * 5
+                )?,
             }
         }
-        if let Some(line_text) = loaded_source.text.get(pos.line.wrapping_sub(1)) {
-            let s: String = line_text.chars().skip(pos.column).collect();
-            source_parts.add(s, &indexes);
+        Ok(())
+    }
+    /// Renders only the generated code panel of a token stream, as the rows
+    /// written in place of `{LEFT}` in the template.
+    ///
+    /// No source files are read, so it works without them. The colors of the
+    /// ranges aren't included, but the spans have the same classes as in a
+    /// full report.
+    pub fn build_left_panel_html(&self, token_stream: TokenStream) -> String {
+        let ranges = generate_ranges(token_stream, self);
+        let mut res = Vec::new();
+        self.write_left(&mut res, &ranges, |_, chunk| self.highlight_class(chunk))
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(res).expect("HTML output is always valid UTF-8")
+    }
+    /// Renders the span mapping of a token stream as the separate parts of
+    /// an HTML report, for embedding in a page of your own.
+    ///
+    /// Returns [`SpanrError::NoSpans`] if none of the tokens could be mapped
+    /// back to a real source file.
+    pub fn build_html_fragment(
+        &self,
+        token_stream: TokenStream,
+    ) -> Result<HtmlFragment, SpanrError> {
+        self.check_palette()?;
+        let ranges = generate_ranges(token_stream, self);
+        if ranges.range_list.is_empty() {
+            return Err(SpanrError::NoSpans);
         }
-        source_parts.add_unspanned("\n");
+        let (source_parts, errors) = load_original_source(&ranges, self);
+        let mut left = b"<div id=\"left\">".to_vec();
+        self.write_left(&mut left, &ranges, |_, chunk| self.highlight_class(chunk))?;
+        left.extend_from_slice(b"</div>");
+        let mut right = b"<div id=\"right\">".to_vec();
+        self.write_right(&mut right, &ranges, &source_parts, &errors)?;
+        right.extend_from_slice(b"</div>");
+        let mut style = STYLE_CSS.as_bytes().to_vec();
+        self.write_range_colors(&mut style, &ranges)?;
+        let utf8 = |bytes| String::from_utf8(bytes).expect("HTML output is always valid UTF-8");
+        Ok(HtmlFragment {
+            left: utf8(left),
+            right: utf8(right),
+            style: utf8(style),
+            script: SCRIPT_JS.into(),
+            features: self.features(),
+        })
     }
-    source_parts
-}
-
-fn generate_html_parts<T: IntoIterator<Item = usize>>(parts: Vec<(String, T)>) -> String {
-    let mut res = String::new();
-    res += "<div>";
-    for (text, indexes) in parts {
-        if text == "\n" {
-            res += "</div><div>";
-        } else {
-            res += "<span class=\"";
-            for idx in indexes {
-                res += &format!("c{} ", idx);
+    /// Writes a report of `ranges`, tagging each chunk of generated code with
+    /// the CSS class returned by `chunk_class` for it and its index.
+    ///
+    /// If `strict` is set, source files that can't be read are an error
+    /// rather than a warning in the report.
+    fn write_report<W: Write>(
+        &self,
+        ranges: Ranges,
+        chunk_class: impl Fn(usize, &Chunk) -> Option<&'static str>,
+        strict: bool,
+        mut out: W,
+    ) -> Result<(), SpanrError> {
+        for &name in REQUIRED_PLACEHOLDERS {
+            if !self.template.contains(&format!("{{{}}}", name)) {
+                return Err(SpanrError::MissingPlaceholder(name));
+            }
+        }
+        self.check_palette()?;
+        if ranges.range_list.is_empty() {
+            return Err(SpanrError::NoSpans);
+        }
+        let (source_parts, errors) = load_original_source(&ranges, self);
+        if strict && !errors.is_empty() {
+            return Err(SpanrError::UnreadableSources(errors));
+        }
+        write_template(&mut out, &self.template, |out, name| {
+            match name {
+                "LEFT" => self.write_left(out, &ranges, &chunk_class)?,
+                "RIGHT" => self.write_right(out, &ranges, &source_parts, &errors)?,
+                "PALETTE" => self.write_palette(out, &ranges)?,
+                _ => return self.write_placeholder(out, name),
+            }
+            Ok(true)
+        })?;
+        out.flush()?;
+        Ok(())
+    }
+    /// Writes the `<style>` element giving each range its color.
+    fn write_palette(&self, out: &mut impl Write, ranges: &Ranges) -> io::Result<()> {
+        out.write_all(b"<style>")?;
+        self.write_range_colors(out, ranges)?;
+        out.write_all(b"</style>")
+    }
+    /// Writes the value of a template placeholder that doesn't depend on the
+    /// token stream, returning whether `name` is one.
+    fn write_placeholder(&self, out: &mut impl Write, name: &str) -> io::Result<bool> {
+        match name {
+            "STYLE" => out.write_all(STYLE_CSS.as_bytes())?,
+            "SCRIPT" => out.write_all(SCRIPT_JS.as_bytes())?,
+            "SEARCH" => {
+                if self.search {
+                    out.write_all(SEARCH_HTML.as_bytes())?;
+                }
+            }
+            "FEATURES" => out.write_all(self.features().as_bytes())?,
+            "FOOTER" => {
+                if self.footer {
+                    write!(
+                        out,
+                        "<div id=\"footer\">spanr v{}",
+                        env!("CARGO_PKG_VERSION")
+                    )?;
+                    if self.timestamp {
+                        write!(out, ", generated {}", format_timestamp(SystemTime::now()))?;
+                    }
+                    out.write_all(b"</div>")?;
+                }
             }
-            res += "\">";
-            res += &encode_text(&text);
-            res += "</span>"
+            "THEME" => out.write_all(self.theme.name().as_bytes())?,
+            _ => return Ok(false),
         }
+        Ok(true)
     }
-    res += "</div>";
-    res
+    /// Renders the span mapping of a token stream and saves it to a file.
+    ///
+    /// The report is rendered before the file is created, so a report that
+    /// can't be rendered leaves any existing file untouched.
+    pub fn save(
+        &self,
+        token_stream: TokenStream,
+        path: impl AsRef<Path>,
+    ) -> Result<(), SpanrError> {
+        let html = self.build_html(token_stream)?;
+        fs::write(path, html)?;
+        Ok(())
+    }
+    /// Renders the span mapping of a token stream and saves it to a file
+    /// named `file_name` in the `OUT_DIR` of the running build script,
+    /// returning the path written.
+    ///
+    /// Fails with a [`SpanrError::Io`] error of kind
+    /// [`io::ErrorKind::NotFound`] if `OUT_DIR` isn't set, as it is only set
+    /// for build scripts.
+    pub fn save_in_out_dir(
+        &self,
+        token_stream: TokenStream,
+        file_name: &str,
+    ) -> Result<PathBuf, SpanrError> {
+        let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "`OUT_DIR` is not set (is this being called from a build script?)",
+            )
+        })?;
+        let path = Path::new(&out_dir).join(file_name);
+        self.save(token_stream, &path)?;
+        Ok(path)
+    }
+}
+
+/// Walks a token stream and computes its span mapping, loading the
+/// referenced source files from disk.
+pub fn build_span_map(token_stream: TokenStream) -> SpanMap {
+    Spanr::default().build_span_map(token_stream)
+}
+
+/// Finds the tokens of the pretty-printed generated code of a token stream
+/// that have no span pointing into a real source file, as described in
+/// [`SpanMap::synthetic_regions`].
+pub fn synthetic_regions(token_stream: TokenStream) -> Vec<(usize, usize, String)> {
+    build_span_map(token_stream).synthetic_regions()
+}
+
+/// Pairs the text of each token of the pretty-printed generated code of a
+/// token stream with the source text its span points at, if any.
+///
+/// This is convenient for checking in tests that a macro spans its output
+/// where expected.
+pub fn mapped_pairs(token_stream: TokenStream) -> Vec<(String, Option<String>)> {
+    Spanr::default().mapped_pairs(token_stream)
+}
+
+/// Pretty-prints a token stream as it appears in the generated panel of a
+/// report, ignoring spans.
+pub fn pretty_print(token_stream: TokenStream) -> String {
+    Spanr::default().pretty_print(token_stream)
+}
+
+/// Counts the sources, ranges and tokens of the span mapping of a token
+/// stream.
+pub fn stats(token_stream: TokenStream) -> SpanStats {
+    Spanr::default().stats(token_stream)
+}
+
+/// Lists the source files the tokens of a token stream are spanned to, in
+/// order of their path.
+pub fn referenced_sources(token_stream: TokenStream) -> Vec<PathBuf> {
+    Spanr::default().referenced_sources(token_stream)
+}
+
+/// Serializes the span mapping of a token stream as JSON, using the schema
+/// described in the [`json`] module.
+#[cfg(feature = "serde")]
+pub fn to_json(token_stream: TokenStream) -> String {
+    build_span_map(token_stream).to_json()
+}
+
+/// Renders the span mapping of a token stream as an HTML document.
+///
+/// Returns [`SpanrError::NoSpans`] if none of the tokens could be mapped back
+/// to a real source file, rather than producing an empty source panel.
+pub fn generate_html(token_stream: TokenStream) -> Result<String, SpanrError> {
+    Spanr::default().build_html(token_stream)
+}
+
+/// Renders the span mapping of a token stream as an HTML document, streaming
+/// it to `out` as it is produced.
+pub fn write_html<W: Write>(token_stream: TokenStream, out: W) -> Result<(), SpanrError> {
+    Spanr::default().write_html(token_stream, out)
+}
+
+/// Renders the span mapping of a syntax tree, such as a `syn::File` or
+/// `syn::ItemFn`, as an HTML document.
+#[cfg(feature = "syn")]
+pub fn generate_html_from_syn<T: quote::ToTokens + ?Sized>(node: &T) -> Result<String, SpanrError> {
+    generate_html(node.to_token_stream())
+}
+
+/// Finds the source text between ranges of a token stream that isn't covered
+/// by any of them, ignoring whitespace and comments.
+pub fn uncovered_spans(token_stream: TokenStream) -> Vec<(PathBuf, Position, Position)> {
+    Spanr::default().uncovered_spans(token_stream)
+}
+
+/// Finds the ranges of a token stream that several tokens with different text
+/// are spanned to, which suggests `Span::call_site()` was used rather than
+/// the span of the input they were generated from.
+pub fn suspicious_spans(token_stream: TokenStream) -> Vec<(PathBuf, Position, Position, usize)> {
+    Spanr::default().suspicious_spans(token_stream)
+}
+
+/// Renders the span mapping of a token stream as an HTML document, failing if
+/// any of the referenced source files can't be read.
+pub fn try_generate_html(token_stream: TokenStream) -> Result<String, SpanrError> {
+    Spanr::default().try_build_html(token_stream)
 }
 
-pub fn generate_html(token_stream: TokenStream) -> String {
-    let ranges = generate_ranges(token_stream);
-    let source_parts = load_original_source(&ranges);
-    let lhs = generate_html_parts(ranges.generated);
-    let rhs = generate_html_parts(source_parts.parts);
-    TEMPLATE_HTML
-        .replace("{LEFT}", &lhs)
-        .replace("{RIGHT}", &rhs)
+/// Renders the span mapping of a token stream as an HTML document, reading
+/// source files through `cache`.
+pub fn generate_html_with_cache(
+    token_stream: TokenStream,
+    cache: &SourceCache,
+) -> Result<String, SpanrError> {
+    Spanr::default()
+        .source_cache(cache.clone())
+        .build_html(token_stream)
 }
 
-pub fn save_html(token_stream: TokenStream, path: impl AsRef<Path>) -> Result<(), std::io::Error> {
-    let html = generate_html(token_stream);
-    fs::write(path, html)
+/// Renders the span mapping of several labeled token streams as one HTML
+/// document, with a section of generated code for each.
+pub fn generate_html_multi(
+    streams: impl IntoIterator<Item = (String, TokenStream)>,
+) -> Result<String, SpanrError> {
+    Spanr::default().build_html_multi(streams)
+}
+
+/// Renders an HTML report for Rust code given as a string, attributing its
+/// spans to `virtual_path` and using `code` itself as the contents of that
+/// file.
+///
+/// No files are read, so this works where there is no filesystem, such as
+/// in the browser.
+#[cfg(feature = "wasm")]
+pub fn generate_html_str(code: &str, virtual_path: &str) -> Result<String, SpanrError> {
+    let token_stream: TokenStream = code.parse().map_err(SpanrError::Lex)?;
+    Spanr::default()
+        .parsed_source(virtual_path, code)
+        .build_html(token_stream)
+}
+
+/// Renders the span mapping of a token stream as the separate parts of an
+/// HTML report, for embedding in a page of your own.
+pub fn generate_html_fragment(token_stream: TokenStream) -> Result<HtmlFragment, SpanrError> {
+    Spanr::default().build_html_fragment(token_stream)
+}
+
+/// Renders only the generated code panel of a token stream, without reading
+/// any source files.
+pub fn generate_left_panel_html(token_stream: TokenStream) -> String {
+    Spanr::default().build_left_panel_html(token_stream)
+}
+
+/// Renders the span mapping of a token stream and saves it to a file.
+pub fn save_html(token_stream: TokenStream, path: impl AsRef<Path>) -> Result<(), SpanrError> {
+    Spanr::default().save(token_stream, path)
+}
+
+/// Renders the span mapping of a token stream generated by a build script
+/// and saves it to a file named `file_name` in `OUT_DIR`, returning the path
+/// written.
+///
+/// Fails if `OUT_DIR` isn't set, as happens outside of build scripts.
+pub fn save_html_in_out_dir(
+    token_stream: TokenStream,
+    file_name: &str,
+) -> Result<PathBuf, SpanrError> {
+    Spanr::default().save_in_out_dir(token_stream, file_name)
+}
+
+/// Renders the span mapping of a token stream received by a procedural macro
+/// and saves it to a file.
+///
+/// This must be called from within a procedural macro, as `proc_macro` is
+/// only usable there.
+#[cfg(feature = "proc-macro")]
+pub fn save_html_pm(
+    token_stream: proc_macro::TokenStream,
+    path: impl AsRef<Path>,
+) -> Result<(), SpanrError> {
+    save_html(token_stream.into(), path)
 }
 
 #[cfg(test)]
 mod tests {
-    use proc_macro2::TokenStream;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
+
+    use super::{Position, Ranges, SourceParts};
+
+    /// Writes `src` to a temporary file, returning its path.
+    fn temp_source(name: &str, src: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spanr-{}-{}.rs", std::process::id(), name));
+        fs::write(&path, src).unwrap();
+        path
+    }
+
+    /// Writes `src` to a temporary file, and adds a range to that file for
+    /// every token in `src`. Returns the ranges and the text of each token.
+    #[cfg(feature = "span-locations")]
+    fn token_ranges(name: &str, src: &str) -> (Ranges, Vec<String>) {
+        fn visit(ts: TokenStream, path: &PathBuf, ranges: &mut Ranges, texts: &mut Vec<String>) {
+            for tt in ts {
+                let (start, end) = (tt.span().start(), tt.span().end());
+                if let TokenTree::Group(group) = &tt {
+                    visit(group.stream(), path, ranges, texts);
+                }
+                let idx = ranges.add_range(path.clone(), start.into(), end.into());
+                if idx == texts.len() {
+                    texts.push(tt.to_string());
+                }
+            }
+        }
+        let path = temp_source(name, src);
+        let mut ranges = Ranges::default();
+        let mut texts = Vec::new();
+        visit(src.parse().unwrap(), &path, &mut ranges, &mut texts);
+        (ranges, texts)
+    }
+
+    #[test]
+    fn canonical_sources() {
+        let root = std::env::temp_dir().join(format!("spanr-{}-canonical", std::process::id()));
+        for dir in ["a", "b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("lib.rs"), "fn foo() {}").unwrap();
+        }
+        let pos = |column| Position { line: 1, column };
+        let mut ranges = Ranges::default();
+        let a = ranges.add_range(root.join("a/lib.rs"), pos(0), pos(2));
+        let a_again = ranges.add_range(root.join("b/../a/lib.rs"), pos(3), pos(6));
+        let b = ranges.add_range(root.join("b/lib.rs"), pos(0), pos(2));
+        // The same file through another path is the same source, but a file
+        // with the same name elsewhere is not.
+        assert_eq!(ranges.source_list.len(), 2);
+        assert_eq!(
+            ranges.range_list[a].source,
+            ranges.range_list[a_again].source
+        );
+        assert_ne!(ranges.range_list[a].source, ranges.range_list[b].source);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Concatenates the source text covered by range `idx`.
+    fn covered_text(source_parts: &SourceParts, idx: usize) -> String {
+        source_parts
+            .parts
+            .iter()
+            .filter(|(_, indexes)| indexes.contains(&idx))
+            .map(|(text, _)| text.as_str())
+            .collect()
+    }
+
+    /// Checks that every range covers exactly the text of its token.
+    #[cfg(feature = "span-locations")]
+    fn assert_ranges_cover_tokens(name: &str, src: &str) {
+        let (ranges, texts) = token_ranges(name, src);
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        for (idx, text) in texts.iter().enumerate() {
+            if !text.contains(['(', '[', '{']) {
+                assert_eq!(&covered_text(&source_parts, idx), text);
+            }
+        }
+    }
 
     #[test]
     fn it_works() {
         let x: TokenStream = "fn foo() -> i32 {\n42\n}\n".parse().unwrap();
         dbg!(x);
     }
+
+    #[test]
+    fn span_map_generated() {
+        let x: TokenStream = "fn foo() -> i32 {\n42\n}\n".parse().unwrap();
+        let span_map = super::build_span_map(x);
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "fn foo() -> i32 {\n    42\n}\n");
+    }
+
+    /// Pretty-prints `src` with the default options.
+    fn pretty(src: &str) -> String {
+        super::pretty_print(src.parse().unwrap())
+    }
+
+    #[test]
+    fn pretty_print() {
+        let x: TokenStream = "struct Foo { x: u8 }".parse().unwrap();
+        assert_eq!(
+            super::pretty_print(x.clone()),
+            "struct Foo {\n    x: u8\n}\n"
+        );
+        assert_eq!(
+            super::Spanr::new().indent_unit("\t").pretty_print(x),
+            "struct Foo {\n\tx: u8\n}\n"
+        );
+    }
+
+    #[test]
+    fn operator_spacing() {
+        assert_eq!(pretty("a::b::<T>()"), "a::b::<T>()");
+        assert_eq!(pretty("x.y.z"), "x.y.z");
+        assert_eq!(pretty("fn f() -> T"), "fn f() -> T");
+        assert_eq!(pretty("match x { A => b }"), "match x {\n    A => b\n}\n");
+        assert_eq!(pretty("x[a..b]"), "x[a..b]");
+        assert_eq!(pretty("(0..=9)"), "(0..=9)");
+        assert_eq!(pretty("a&&b||c"), "a && b || c");
+        assert_eq!(pretty("a<<b>>c"), "a << b >> c");
+        assert_eq!(pretty("x = -&*y"), "x = -&*y");
+        assert_eq!(pretty("x - y"), "x - y");
+        assert_eq!(pretty("foo!(x)"), "foo!(x)");
+        assert_eq!(pretty("if !x"), "if !x");
+        assert_eq!(pretty("f(x?, 'a)"), "f(x?, 'a)");
+        assert_eq!(pretty("::std::mem"), "::std::mem");
+    }
+
+    #[test]
+    fn joint_operators() {
+        assert_eq!(pretty("x += 1"), "x += 1");
+        assert_eq!(pretty("a == b"), "a == b");
+        assert_eq!(pretty("a != b"), "a != b");
+        assert_eq!(pretty("x-=1"), "x -= 1");
+        assert_eq!(pretty("a <= b && c >= d"), "a <= b && c >= d");
+        assert_eq!(pretty("N <= M"), "N <= M");
+        assert_eq!(pretty("Foo <= Bar"), "Foo <= Bar");
+        assert_eq!(pretty("x = !y"), "x = !y");
+    }
+
+    #[test]
+    fn where_clauses() {
+        let broken = |src: &str| {
+            let config = super::Spanr::new().break_where_clauses(true);
+            let ranges = super::generate_ranges(src.parse().unwrap(), &config);
+            let newlines_unspanned = ranges
+                .generated
+                .iter()
+                .filter(|chunk| chunk.text == "\n")
+                .all(|chunk| chunk.range.is_none());
+            assert!(newlines_unspanned);
+            ranges
+                .generated
+                .iter()
+                .map(|chunk| chunk.text.as_str())
+                .collect::<String>()
+        };
+        assert_eq!(
+            broken(
+                "impl<T, U> Foo for Bar<T, U> where T: Into<Vec<U>>, U: Fn(u8, u8) { fn f() {} }"
+            ),
+            "impl<T, U> Foo for Bar<T, U>\n\
+             where\n    \
+             T: Into<Vec<U>>,\n    \
+             U: Fn(u8, u8)\n\
+             {\n    \
+             fn f() {\n    \
+             }\n\
+             }\n"
+        );
+        assert_eq!(
+            broken("trait Foo { fn f<T>() where T: Clone; fn g(); }"),
+            "trait Foo {\n    \
+             fn f<T>()\n    \
+             where\n        \
+             T: Clone;\n    \
+             fn g();\n\
+             }\n"
+        );
+        // By default, the clause stays on the line of the signature.
+        assert_eq!(
+            pretty("fn f<T>() where T: Clone, {}"),
+            "fn f<T>() where T: Clone, {\n}\n"
+        );
+    }
+
+    #[test]
+    fn generics_spacing() {
+        assert_eq!(pretty("x: HashMap<K, V>"), "x: HashMap<K, V>");
+        assert_eq!(pretty("foo::<u32>()"), "foo::<u32>()");
+        assert_eq!(pretty("Vec<Vec<u8>>::new()"), "Vec<Vec<u8>>::new()");
+        assert_eq!(pretty("fn foo<T>(x: T)"), "fn foo<T>(x: T)");
+        assert_eq!(
+            pretty("impl<'a, T: Into<String>> Foo<'a> for T"),
+            "impl<'a, T: Into<String>> Foo<'a> for T"
+        );
+        assert_eq!(
+            pretty("<T as Default>::default()"),
+            "<T as Default>::default()"
+        );
+        assert_eq!(pretty("a < b && c > d"), "a < b && c > d");
+        assert_eq!(pretty("if LEN < MAX"), "if LEN < MAX");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn syntax_highlight() {
+        let src = "fn foo<'a>(x: &'a str) -> u8 { bar(\"s\", 'c'); 1 }";
+        let html = |syntax_highlight| {
+            let x: TokenStream = src.parse().unwrap();
+            super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .syntax_highlight(syntax_highlight)
+                .build_html(x)
+                .unwrap()
+        };
+        let highlighted = html(true);
+        let attrs = "data-source=\"0\" data-line=\"1\"";
+        let contains = |html: &str| highlighted.contains(&html.replace("{}", attrs));
+        assert!(contains("<span class=\"c0 s0 kw\" {}>fn</span>"));
+        assert!(contains(
+            " lifetime\" {}>'</span><span class=\"c4 s0 lifetime\" {}>a</span>"
+        ));
+        assert!(contains(" lit-str\" {}>\"s\"</span>"));
+        assert!(contains(" lit-str\" {}>'c'</span>"));
+        assert!(contains(" lit-num\" {}>1</span>"));
+        assert!(contains("<span class=\"c1 s0 \" {}>foo</span>"));
+        assert!(!html(false).contains(" kw\""));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn original_literals() {
+        let src = "f(0XFF, \"multi\nline\")";
+        // Re-create the literals, as a macro might, keeping only their spans.
+        let respan = |tt: TokenTree| match tt {
+            TokenTree::Literal(lit) if lit.to_string() == "0XFF" => {
+                let mut new = proc_macro2::Literal::u8_suffixed(255);
+                new.set_span(lit.span());
+                new.into()
+            }
+            tt => tt,
+        };
+        let tokens = || -> TokenStream {
+            let x: TokenStream = src.parse().unwrap();
+            x.into_iter()
+                .map(|tt| match tt {
+                    TokenTree::Group(group) => proc_macro2::Group::new(
+                        group.delimiter(),
+                        group.stream().into_iter().map(respan).collect(),
+                    )
+                    .into(),
+                    tt => tt,
+                })
+                .collect()
+        };
+        let generated = |config: super::Spanr| -> String {
+            let ranges = super::generate_ranges(tokens(), &config);
+            ranges
+                .generated
+                .iter()
+                .map(|chunk| chunk.text.as_str())
+                .collect()
+        };
+        let config = || super::Spanr::new().original_literals(true);
+        assert_eq!(
+            generated(config().parsed_source("foo.rs", src)),
+            "f(0XFF, \"multi\nline\")"
+        );
+        assert_eq!(
+            generated(super::Spanr::new().parsed_source("foo.rs", src)),
+            "f(255u8, \"multi\nline\")"
+        );
+        assert_eq!(generated(config()), "f(255u8, \"multi\nline\")");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn preserve_formatting() {
+        let src = "fn foo( a: u8 ) -> u8 {\n  a /* same */ + 0x1\n}";
+        let original: TokenStream = src.parse().unwrap();
+        // Splice in a token without a span, as a macro might.
+        let tokens: TokenStream = original
+            .into_iter()
+            .chain(
+                "struct Bar;"
+                    .parse::<TokenStream>()
+                    .unwrap()
+                    .into_iter()
+                    .map(|mut tt| {
+                        tt.set_span(proc_macro2::Span::call_site());
+                        tt
+                    }),
+            )
+            .collect();
+        let config = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .preserve_formatting(true);
+        let ranges = super::generate_ranges(tokens, &config);
+        let generated: String = ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(
+            generated,
+            "fn foo( a: u8 ) -> u8 {\n  a /* same */ + 0x1\n}\nstruct Bar;\n"
+        );
+        let comment = ranges
+            .generated
+            .iter()
+            .find(|chunk| chunk.text.contains("same"))
+            .unwrap();
+        assert_eq!(comment.range, None);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn multiple_streams() {
+        let src = "struct Foo;";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let streams = ["first", "second"].map(|label| (label.to_string(), src.parse().unwrap()));
+        let ranges = super::generate_ranges_multi(streams, &config);
+        let generated: String = ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        let rule = super::SECTION_RULE;
+        assert_eq!(
+            generated,
+            format!(
+                "{rule}\n// first\n{rule}\nstruct Foo;\n\n{rule}\n// second\n{rule}\nstruct Foo;\n"
+            )
+        );
+        // Both sections share the ranges of the tokens they have in common.
+        assert_eq!(ranges.range_list.len(), 3);
+        let foo: Vec<_> = ranges
+            .generated
+            .iter()
+            .filter(|chunk| chunk.text == "Foo")
+            .map(|chunk| chunk.range)
+            .collect();
+        assert_eq!(foo.len(), 2);
+        assert_eq!(foo[0], foo[1]);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn suspicious_spans() {
+        let src = "foo!(x)";
+        let input: Vec<TokenTree> = src.parse::<TokenStream>().unwrap().into_iter().collect();
+        let call_site = input[0].span();
+        let generated: TokenStream = "let a = b + x;"
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .map(|mut tt| {
+                tt.set_span(call_site);
+                tt
+            })
+            .collect();
+        // The input identifier is used several times, which is fine.
+        let x = match &input[2] {
+            TokenTree::Group(group) => group.stream(),
+            _ => unreachable!(),
+        };
+        let mut tokens = generated;
+        tokens.extend([x.clone(), x.clone(), x]);
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let pos = |column| Position { line: 1, column };
+        assert_eq!(
+            config.suspicious_spans(tokens.clone()),
+            [("foo.rs".into(), pos(0), pos(3), 7)]
+        );
+        assert!(super::suspicious_spans(tokens).is_empty());
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn uncovered_spans() {
+        // The gap between `e` and `f` holds only a comment, so isn't reported.
+        let src = "a b\n  c\n d e /* g */ f";
+        let tokens = || -> TokenStream {
+            let x: TokenStream = src.parse().unwrap();
+            x.into_iter()
+                .filter(|tt| !matches!(tt.to_string().as_str(), "b" | "c" | "d"))
+                .collect()
+        };
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let pos = |line, column| Position { line, column };
+        assert_eq!(
+            config.uncovered_spans(tokens()),
+            [("foo.rs".into(), pos(1, 2), pos(3, 2))]
+        );
+        assert!(super::uncovered_spans(tokens()).is_empty());
+
+        let html = config.coverage(true).build_html(tokens()).unwrap();
+        assert!(html.contains("<span class=\"unused\"> b</span>"));
+        assert!(html.contains("<span class=\"unused\"> d </span>"));
+        // Only the lines of the reported gap are unused, not the comment.
+        assert!(html.contains("<span class=\"unused\">  c</span>"));
+        assert!(html.contains(" /* g */ "));
+        assert_eq!(html.matches("class=\"unused\"").count(), 3);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn comments_unspanned() {
+        // The range of the group covers the comment, along with `a` and `b`.
+        let src = "(a /* comment */ b) // trailing";
+        let (ranges, texts) = token_ranges("comments_unspanned", src);
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        let comment = source_parts
+            .parts
+            .iter()
+            .find(|(text, _)| text.contains("/*"))
+            .unwrap();
+        assert_eq!(comment, &(" /* comment */ ".to_string(), vec![]));
+        let trailing = source_parts
+            .parts
+            .iter()
+            .find(|(text, _)| text.contains("// trailing"))
+            .unwrap();
+        assert!(trailing.1.is_empty());
+        let group = texts.iter().position(|text| text.starts_with('(')).unwrap();
+        assert_eq!(covered_text(&source_parts, group), "(ab)");
+
+        // Comments aren't reported as dropped input.
+        let src = "f(a /* comment */, b)";
+        let config = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .coverage(true);
+        let html = config.build_html(src.parse().unwrap()).unwrap();
+        assert!(html.contains("<span class=\"\"> /* comment */</span>"));
+    }
+
+    #[test]
+    fn save() {
+        // A report that can't be rendered doesn't replace the last one.
+        let path = temp_source("save", "previous report");
+        assert!(matches!(
+            super::Spanr::new().save("fn foo() {}".parse().unwrap(), &path),
+            Err(super::SpanrError::NoSpans)
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous report");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn save_in_out_dir() {
+        let src = "fn foo() {}";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        std::env::remove_var("OUT_DIR");
+        match config.save_in_out_dir(src.parse().unwrap(), "report.html") {
+            Err(super::SpanrError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+                assert!(e.to_string().contains("OUT_DIR"));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let out_dir = std::env::temp_dir().join(format!("spanr-{}-out", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        std::env::set_var("OUT_DIR", &out_dir);
+        let path = config
+            .save_in_out_dir(src.parse().unwrap(), "report.html")
+            .unwrap();
+        std::env::remove_var("OUT_DIR");
+        assert_eq!(path, out_dir.join("report.html"));
+        assert!(fs::read_to_string(&path).unwrap().contains(">fn</span>"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn stats() {
+        let src = "fn foo() {}";
+        let tokens: TokenStream = src
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .chain(std::iter::once(TokenTree::from(proc_macro2::Ident::new(
+                "bar",
+                proc_macro2::Span::call_site(),
+            ))))
+            .collect();
+        let stats = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .stats(tokens.clone());
+        assert_eq!(
+            stats,
+            super::SpanStats {
+                sources: 1,
+                ranges: 6,
+                tokens: 7,
+                unspanned_tokens: 1,
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "1 sources, 6 ranges, 7 tokens (1 unspanned)"
+        );
+        assert_eq!(super::stats(tokens).ranges, 0);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn referenced_sources() {
+        let src = "fn foo() {}";
+        let tokens: TokenStream = src.parse().unwrap();
+        let sources = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .referenced_sources(tokens.clone());
+        assert_eq!(sources, [PathBuf::from("foo.rs")]);
+        // Without a path, tokens parsed from a string have no source file.
+        assert!(super::referenced_sources(tokens).is_empty());
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn only_sources() {
+        let src = "fn foo() {}";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let ranges = |config: super::Spanr| config.stats(src.parse().unwrap()).ranges;
+        assert_eq!(ranges(config.clone().only_sources(["foo.rs"])), 6);
+        assert_eq!(ranges(config.clone().only_sources(["bar.rs", "foo.rs"])), 6);
+        assert_eq!(ranges(config.clone().only_sources(["bar.rs"])), 0);
+        assert!(matches!(
+            config
+                .only_sources(["bar.rs"])
+                .build_html(src.parse().unwrap()),
+            Err(super::SpanrError::NoSpans)
+        ));
+
+        // Tests run from the root of the crate, where this path exists, and
+        // spans into it are reported by its canonical path.
+        let config = super::Spanr::new().parsed_source("src/lib.rs", src);
+        assert_eq!(ranges(config.clone().only_sources(["src/lib.rs"])), 6);
+        assert_eq!(ranges(config.only_sources(["./src/../src/lib.rs"])), 6);
+    }
+
+    #[cfg(not(feature = "span-locations"))]
+    #[test]
+    fn without_span_locations() {
+        // No token can be mapped back to its source, even a parsed one.
+        let src = "fn foo() {}";
+        assert!(matches!(
+            super::generate_html(src.parse().unwrap()),
+            Err(super::SpanrError::NoSpans)
+        ));
+        assert!(matches!(
+            super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .build_html(src.parse().unwrap()),
+            Err(super::SpanrError::NoSpans)
+        ));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn only_token_kinds() {
+        use super::TokenKind;
+        let src = "fn foo(x: u8) -> u8 { x + 1 }";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let spanned = |kinds: &[TokenKind]| -> String {
+            config
+                .clone()
+                .only_token_kinds(kinds.iter().copied())
+                .build_span_map(src.parse().unwrap())
+                .chunks()
+                .filter_map(|chunk| match chunk {
+                    super::GeneratedChunk::Spanned(text, _) => Some(text),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(spanned(&[TokenKind::Ident]), "fnfooxu8u8x");
+        assert_eq!(spanned(&[TokenKind::Literal, TokenKind::Punct]), ":->+1");
+        assert_eq!(spanned(&[TokenKind::Group]), "(){}");
+        // Tokens of other kinds are still written.
+        let html = config
+            .only_token_kinds([TokenKind::Literal])
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        assert!(html.contains("foo"));
+        // The only range is `1`, in each panel.
+        assert_eq!(html.matches("class=\"c").count(), 2);
+    }
+
+    #[test]
+    fn range_nesting() {
+        let range = |source, start: (usize, usize), end: (usize, usize)| super::Range {
+            source,
+            start: Position {
+                line: start.0,
+                column: start.1,
+            },
+            end: Position {
+                line: end.0,
+                column: end.1,
+            },
+        };
+        let outer = range(0, (1, 0), (3, 1));
+        let inner = range(0, (1, 0), (1, 5));
+        let point = range(0, (3, 1), (3, 1));
+        let overlapping = range(0, (2, 0), (4, 0));
+        assert!(outer.contains(&inner));
+        assert!(outer.contains(&outer));
+        assert!(outer.contains(&point));
+        assert!(!inner.contains(&outer));
+        assert!(!outer.contains(&overlapping));
+        assert!(!outer.contains(&range(1, (1, 0), (1, 5))));
+
+        let mut ranges = vec![point, inner, overlapping, outer];
+        ranges.sort_by(super::Range::cmp_nesting);
+        assert_eq!(ranges, [outer, inner, overlapping, point]);
+        // Each range comes after every range containing it.
+        for (i, a) in ranges.iter().enumerate() {
+            for b in &ranges[i + 1..] {
+                assert!(!b.contains(a) || a == b);
+            }
+        }
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn self_contained() {
+        // The report is a single file, so it mustn't load anything else.
+        let src = "fn foo() {\n    bar();\n}";
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .search(true)
+            .footer(true)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        for reference in ["src=", "href=", "<link", "@import", "url("] {
+            assert!(!html.contains(reference), "{}", reference);
+        }
+        assert!(html.contains("<style>") && html.contains("<script>"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn nested_spans() {
+        let render = |ranges: &Ranges| {
+            let config = super::Spanr::new().source_header("");
+            let (source_parts, _) = super::load_original_source(ranges, &config);
+            let mut res = Vec::new();
+            config
+                .write_right(&mut res, ranges, &source_parts, &[])
+                .unwrap();
+            String::from_utf8(res).unwrap()
+        };
+        let (mut ranges, _) = token_ranges("nested_spans", "f(a, (b)) c");
+        assert_eq!(
+            render(&ranges),
+            "<div></div><div><span class=\"c0 s0 \">f</span><span class=\"c5 s0 \">(\
+             <span class=\"c1 s0 \">a</span><span class=\"c2 s0 \">,</span> \
+             <span class=\"c4 s0 \">(<span class=\"c3 s0 \">b</span>)</span>)</span>\
+             <span class=\"\"> </span><span class=\"c6 s0 \">c</span><span class=\"\"></span>\
+             </div><div></div>"
+        );
+
+        // A range from `b` to `c` crosses the groups around `b`, so the text
+        // they share falls back to being tagged with all of its ranges.
+        let path = ranges.source_list[0].clone();
+        let pos = |column| Position { line: 1, column };
+        ranges.add_range(path, pos(6), pos(11));
+        assert_eq!(
+            render(&ranges),
+            "<div></div><div><span class=\"c0 s0 \">f</span><span class=\"c5 s0 \">(\
+             <span class=\"c1 s0 \">a</span><span class=\"c2 s0 \">,</span> \
+             <span class=\"c4 s0 \">(</span></span><span class=\"c3 c4 c5 c7 s0 \">b</span>\
+             <span class=\"c4 c5 c7 s0 \">)</span><span class=\"c5 c7 s0 \">)</span>\
+             <span class=\"c7 s0 \"> <span class=\"c6 s0 \">c</span></span>\
+             <span class=\"\"></span></div><div></div>"
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn source_root() {
+        let src = "fn foo() {}";
+        let path = temp_source("source_root", src);
+        let config = super::Spanr::new().parsed_source(&path, src);
+        let ranges = |config: &super::Spanr| super::generate_ranges(src.parse().unwrap(), config);
+        let inside = ranges(&config.clone().source_root(std::env::temp_dir()));
+        assert_eq!(inside.range_list.len(), 6);
+        assert!(inside.outside_root.is_empty());
+
+        let config = config.source_root(env!("CARGO_MANIFEST_DIR"));
+        let outside = ranges(&config);
+        assert!(outside.range_list.is_empty());
+        let canonical = fs::canonicalize(&path).unwrap();
+        assert!(outside.outside_root.contains(&canonical));
+        let mut out = Vec::new();
+        config
+            .write_right(&mut out, &outside, &SourceParts::default(), &[])
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.starts_with("<div class=\"warning\">warning: left out spans into "));
+    }
+
+    #[test]
+    fn line_zero_ranges() {
+        let path = temp_source("line_zero_ranges", "x\n");
+        let mut ranges = Ranges::default();
+        let zero = Position { line: 0, column: 0 };
+        ranges.add_range(path.clone(), zero, Position { line: 0, column: 1 });
+        let (source_parts, errors) = super::load_original_source(&ranges, &super::Spanr::new());
+        assert!(errors.is_empty());
+        assert!(source_parts
+            .parts
+            .iter()
+            .all(|(_, indexes)| indexes.is_empty()));
+
+        let start = Position { line: 1, column: 0 };
+        let x = ranges.add_range(path, start, Position { line: 1, column: 1 });
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        assert_eq!(covered_text(&source_parts, 0), "");
+        assert_eq!(covered_text(&source_parts, x), "x");
+
+        // Spans without locations never get as far as a range.
+        let synthetic: TokenStream =
+            TokenTree::from(proc_macro2::Ident::new("x", Span::call_site())).into();
+        assert_eq!(super::stats(synthetic).ranges, 0);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn source_header() {
+        let (ranges, _) = token_ranges("source_header", "x");
+        let source_text = |config: super::Spanr| -> String {
+            let (source_parts, _) = super::load_original_source(&ranges, &config);
+            source_parts
+                .parts
+                .iter()
+                .map(|(text, _)| text.as_str())
+                .collect()
+        };
+        let path = ranges.source_list[0].display().to_string();
+        assert_eq!(
+            source_text(super::Spanr::new()),
+            format!(
+                "\n{rule}\n// {}\n{rule}\nx\n",
+                path,
+                rule = super::SECTION_RULE
+            )
+        );
+        assert_eq!(
+            source_text(super::Spanr::new().source_header("# {path}")),
+            format!("\n# {}\nx\n", path)
+        );
+        assert_eq!(source_text(super::Spanr::new().source_header("")), "\nx\n");
+
+        let relative = |config: super::Spanr| source_text(config.source_header("// {path}"));
+        let name = ranges.source_list[0].file_name().unwrap().to_str().unwrap();
+        let dir = ranges.source_list[0].parent().unwrap().to_owned();
+        assert_eq!(
+            relative(super::Spanr::new().relative_paths_to(&dir)),
+            format!("\n// {}\nx\n", name)
+        );
+        // Paths outside of the base are shown in full.
+        assert_eq!(
+            relative(super::Spanr::new().relative_paths_to(dir.join("elsewhere"))),
+            format!("\n// {}\nx\n", path)
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn synthetic_regions() {
+        let src = "fn foo() {}";
+        let body: TokenStream = "let x = 1;"
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .map(|mut tt| {
+                tt.set_span(Span::call_site());
+                tt
+            })
+            .collect();
+        let tokens: TokenStream = src
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .map(|tt| match tt {
+                TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                    let mut res = proc_macro2::Group::new(Delimiter::Brace, body.clone());
+                    res.set_span(group.span());
+                    res.into()
+                }
+                tt => tt,
+            })
+            .collect();
+        let span_map = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(tokens);
+        let regions = span_map.synthetic_regions();
+        assert_eq!(
+            regions,
+            [
+                (2, 4, "let".to_string()),
+                (2, 8, "x".to_string()),
+                (2, 10, "=".to_string()),
+                (2, 12, "1".to_string()),
+                (2, 13, ";".to_string())
+            ]
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn generated_chunks() {
+        use super::GeneratedChunk::{Layout, Spanned, Synthetic};
+        let src = "foo";
+        let tokens: TokenStream = src
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .chain(Some(
+                proc_macro2::Ident::new("bar", Span::call_site()).into(),
+            ))
+            .collect();
+        let span_map = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .group_by_source(true)
+            .build_span_map(tokens);
+        let chunks: Vec<_> = span_map.chunks().collect();
+        assert_eq!(
+            chunks,
+            [
+                Layout("// --- foo.rs ---"),
+                Layout("\n"),
+                Spanned("foo", 0),
+                Layout(" "),
+                Synthetic("bar"),
+            ]
+        );
+        assert_eq!(chunks[2].text(), "foo");
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn generate_html_str() {
+        // The path doesn't exist, so the source must come from `code`.
+        let html = super::generate_html_str("fn foo() {}", "/nonexistent/lib.rs").unwrap();
+        assert!(html.contains("// /nonexistent/lib.rs"));
+        assert!(!html.contains("<div class=\"warning\">"));
+        assert!(html.contains("<span class=\"c1 s0 \">foo</span>"));
+        assert!(matches!(
+            super::generate_html_str("\"unterminated", "lib.rs"),
+            Err(super::SpanrError::Lex(_))
+        ));
+    }
+
+    #[test]
+    fn past_end_ranges() {
+        let path = temp_source("past_end_ranges", "a\nb\n");
+        let mut ranges = Ranges::default();
+        let pos = |line, column| Position { line, column };
+        let a = ranges.add_range(path.clone(), pos(1, 0), pos(1, 1));
+        let b = ranges.add_range(path.clone(), pos(2, 0), pos(1000, 3));
+        let gone = ranges.add_range(path, pos(500, 0), pos(500, 1));
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        assert_eq!(covered_text(&source_parts, a), "a");
+        assert_eq!(covered_text(&source_parts, b), "b");
+        assert_eq!(covered_text(&source_parts, gone), "");
+        let text: String = source_parts
+            .parts
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect();
+        assert!(text.ends_with("a\nb\n// note: 2 range(s) extend past the end of this file\n"));
+        // Every range has ended by the end of the file, leaving only the
+        // marker of the range cut down to nothing.
+        assert!(source_parts
+            .parts
+            .iter()
+            .skip_while(|(text, _)| text != "b")
+            .skip(1)
+            .all(|(text, indexes)| indexes.is_empty() || text.is_empty()));
+    }
+
+    #[test]
+    fn zero_width_ranges() {
+        let path = temp_source("zero_width_ranges", "abc\n");
+        let mut ranges = Ranges::default();
+        let pos = |column| Position { line: 1, column };
+        let outer = ranges.add_range(path.clone(), pos(0), pos(3));
+        let point = ranges.add_range(path.clone(), pos(1), pos(1));
+        let at_start = ranges.add_range(path, pos(0), pos(0));
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        let parts: Vec<_> = source_parts
+            .parts
+            .iter()
+            .skip_while(|(text, _)| !text.is_empty())
+            .take(4)
+            .map(|(text, indexes)| (text.as_str(), indexes.clone()))
+            .collect();
+        // Each zero-width range is marked by an empty part of its own, covered
+        // by the ranges around it too.
+        assert_eq!(
+            parts,
+            [
+                ("", vec![outer, at_start]),
+                ("a", vec![outer]),
+                ("", vec![outer, point]),
+                ("bc", vec![outer]),
+            ]
+        );
+        assert_eq!(source_parts.markers.len(), 2);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn mapped_pairs() {
+        let src = "struct MyStruct(0XFF);";
+        let tokens: TokenStream = src
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .chain(Some(
+                proc_macro2::Ident::new("extra", Span::call_site()).into(),
+            ))
+            .collect();
+        let pairs = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .mapped_pairs(tokens);
+        let pair = |text: &str, source: Option<&str>| (text.to_string(), source.map(String::from));
+        assert_eq!(
+            pairs,
+            [
+                pair("struct", Some("struct")),
+                pair("MyStruct", Some("MyStruct")),
+                pair("(", Some("(")),
+                pair("0XFF", Some("0XFF")),
+                pair(")", Some(")")),
+                pair(";", Some(";")),
+                pair("extra", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_separator_positions() {
+        let src = "a+b";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let pos = |column| Position { line: 1, column };
+        let mut ranges = Ranges::default();
+        let mut add = |start, end| ranges.add_range("foo.rs".into(), pos(start), pos(end));
+        let a = add(0, 1);
+        let plus = add(1, 2);
+        let b = add(2, 3);
+        let a_plus = add(0, 2);
+        let plus_b = add(1, 3);
+        let empty = add(1, 1);
+        let (source_parts, _) = super::load_original_source(&ranges, &config);
+        let indexes_of = |text| {
+            let (_, indexes) = source_parts
+                .parts
+                .iter()
+                .find(|(part, _)| part == text)
+                .unwrap();
+            let mut indexes = indexes.clone();
+            indexes.sort_unstable();
+            indexes
+        };
+        assert_eq!(indexes_of("a"), [a, a_plus]);
+        assert_eq!(indexes_of("+"), [plus, a_plus, plus_b]);
+        assert_eq!(indexes_of("b"), [b, plus_b]);
+        assert_eq!(covered_text(&source_parts, empty), "");
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            pretty("#![allow(dead_code)] #[derive(Clone, Debug)] #[serde(rename = \"foo\")] struct Foo;"),
+            "#![allow(dead_code)]\n#[derive(Clone, Debug)]\n#[serde(rename = \"foo\")]\nstruct Foo;\n"
+        );
+        assert_eq!(
+            pretty("fn foo() { #[cfg(x)] bar(); }"),
+            "fn foo() {\n    #[cfg(x)]\n    bar();\n}\n"
+        );
+        assert_eq!(pretty("foo(#[a] x)"), "foo(#[a] x)");
+        assert_eq!(
+            pretty("let v = vec![1, 2]; println!(\"{:?}\", v);"),
+            "let v = vec![1, 2];\nprintln!(\"{:?}\", v);\n"
+        );
+    }
+
+    #[test]
+    fn closure_spacing() {
+        assert_eq!(pretty("|x| x"), "|x| x");
+        assert_eq!(pretty("a | b"), "a | b");
+        assert_eq!(
+            pretty("f(|x, (a, b)| x + a | b)"),
+            "f(|x, (a, b)| x + a | b)"
+        );
+        assert_eq!(
+            pretty("let f = move |&x: &u8| -x;"),
+            "let f = move |&x: &u8| -x;\n"
+        );
+        assert_eq!(pretty("let f = || 1;"), "let f = || 1;\n");
+        assert_eq!(
+            pretty("match x { A | B => 1 }"),
+            "match x {\n    A | B => 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn max_indent() {
+        let src = "{ { { x } } }";
+        let pretty = |config: super::Spanr| {
+            let ranges = super::generate_ranges(src.parse().unwrap(), &config);
+            ranges
+                .generated
+                .iter()
+                .map(|chunk| chunk.text.as_str())
+                .collect::<String>()
+        };
+        assert_eq!(
+            pretty(super::Spanr::new().max_indent(1)),
+            "{\n    {\n    {\n    x\n    }\n    }\n}\n"
+        );
+        assert_eq!(
+            pretty(super::Spanr::new()),
+            "{\n    {\n        {\n            x\n        }\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn max_width() {
+        let src = "fn foo(aaaa: u8, bbbb: u8, cccc: u8) { bar(1, 2) }";
+        let ranges =
+            super::generate_ranges(src.parse().unwrap(), &super::Spanr::new().max_width(20));
+        let generated: String = ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(
+            generated,
+            "fn foo(aaaa: u8,\n    bbbb: u8, cccc: u8) {\n    bar(1, 2)\n}\n"
+        );
+        assert!(ranges
+            .generated
+            .iter()
+            .filter(|chunk| chunk.text.trim().is_empty())
+            .all(|chunk| chunk.range.is_none()));
+        assert_eq!(
+            pretty(src),
+            "fn foo(aaaa: u8, bbbb: u8, cccc: u8) {\n    bar(1, 2)\n}\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_token_width() {
+        let src = "f(a, \"first line\nx\", b, c);";
+        let ranges =
+            super::generate_ranges(src.parse().unwrap(), &super::Spanr::new().max_width(12));
+        let generated: String = ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        // The width of the line a multi-line token ends on only counts its
+        // last line.
+        assert_eq!(generated, "f(a,\n    \"first line\nx\", b, c);\n");
+    }
+
+    #[test]
+    fn dollar_crate() {
+        // `quote!` turns the `$crate` of the macro into `crate`, spanned to
+        // the call site.
+        macro_rules! path {
+            () => {
+                syn::parse_quote!($crate::foo::bar())
+            };
+        }
+        let ts: TokenStream = path!();
+        let span_map = super::build_span_map(ts);
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "crate::foo::bar()");
+        assert!(span_map.generated().all(|(_, idx)| idx.is_none()));
+
+        // Metavariables and `$crate` in a macro body are kept together.
+        let ts: TokenStream = "fn $name() { $crate::f($x) }".parse().unwrap();
+        assert_eq!(
+            super::pretty_print(ts),
+            "fn $name() {\n    $crate::f($x)\n}\n"
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn sort_by_source_position() {
+        let src = "fn b() {}\nfn a() {}\n";
+        let items: Vec<TokenTree> = src.parse::<TokenStream>().unwrap().into_iter().collect();
+        let synthetic: TokenStream = "struct X;".parse::<TokenStream>().unwrap();
+        // Generate `a` before `b`, with a synthetic item after it.
+        let mut x: TokenStream = items[4..].iter().cloned().collect();
+        x.extend(synthetic.into_iter().map(|mut tt| {
+            tt.set_span(Span::call_site());
+            tt
+        }));
+        x.extend(items[..4].iter().cloned());
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let ranges = super::generate_ranges(x, &config);
+        let text: String = ranges
+            .rows_by_source()
+            .into_iter()
+            .map(|idx| idx.map_or("\n", |idx| ranges.generated[idx].text.as_str()))
+            .collect();
+        assert_eq!(text, "fn b() {\n}\nfn a() {\n}\nstruct X;\n");
+
+        let html = config
+            .sort_by_source_position(true)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        assert!(!html.contains("data-block=\""));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn split_literals() {
+        let src = "f(\"ab\", r#\"c\"#, 'd', 1, \"\");";
+        let spanned = |config: super::Spanr| {
+            let config = config.parsed_source("foo.rs", src);
+            let ranges = super::generate_ranges(src.parse().unwrap(), &config);
+            ranges
+                .generated
+                .iter()
+                .filter_map(|chunk| {
+                    let range = ranges.range_list[chunk.range?];
+                    Some((chunk.text.clone(), range.start.column, range.end.column))
+                })
+                .filter(|(text, _, _)| text.contains(['"', '\'', 'a', 'c', 'd']))
+                .collect::<Vec<_>>()
+        };
+        let part = |text: &str, start, end| (text.to_string(), start, end);
+        // By default, the whole literal is spanned to the whole source literal.
+        assert_eq!(
+            spanned(super::Spanr::new()),
+            [
+                part("\"ab\"", 2, 6),
+                part("r#\"c\"#", 8, 14),
+                part("'d'", 16, 19),
+                part("\"\"", 24, 26),
+            ]
+        );
+        assert_eq!(
+            spanned(super::Spanr::new().split_literals(true)),
+            [
+                part("\"", 2, 3),
+                part("ab", 3, 5),
+                part("\"", 5, 6),
+                part("r#\"", 8, 11),
+                part("c", 11, 12),
+                part("\"#", 12, 14),
+                part("'", 16, 17),
+                part("d", 17, 18),
+                part("'", 18, 19),
+                part("\"", 24, 25),
+                part("\"", 25, 26),
+            ]
+        );
+        // The whole literals aren't given ranges of their own.
+        let config = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .split_literals(true);
+        let ranges = super::generate_ranges(src.parse().unwrap(), &config);
+        let used: std::collections::BTreeSet<_> = ranges
+            .generated
+            .iter()
+            .filter_map(|chunk| chunk.range)
+            .collect();
+        assert_eq!(used.len(), ranges.range_list.len());
+        assert_eq!(config.stats(src.parse().unwrap()).ranges, 20);
+    }
+
+    #[test]
+    fn source_overrides() {
+        let path = temp_source("source_overrides", "on disk\n");
+        let mut ranges = Ranges::default();
+        let pos = |column| Position { line: 1, column };
+        let idx = ranges.add_range(path.clone(), pos(0), pos(2));
+        let virtual_idx = ranges.add_range("virtual.rs".into(), pos(3), pos(7));
+        let config = super::Spanr::new().source_overrides([
+            (path, "in memory\n".to_string()),
+            ("virtual.rs".into(), "fn main() {}\n".to_string()),
+        ]);
+        let (source_parts, _) = super::load_original_source(&ranges, &config);
+        assert_eq!(covered_text(&source_parts, idx), "in");
+        assert_eq!(covered_text(&source_parts, virtual_idx), "main");
+    }
+
+    #[test]
+    fn leading_line_content() {
+        let src = "fn a() {\n    let x = foo();\n}\n";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let pos = |line, column| Position { line, column };
+        let source_text = |ranges: &[(Position, Position)]| {
+            let mut map = Ranges::default();
+            for &(start, end) in ranges {
+                map.add_range("foo.rs".into(), start, end);
+            }
+            let (source_parts, _) = super::load_original_source(&map, &config);
+            source_parts
+                .parts
+                .iter()
+                .map(|(text, _)| text.as_str())
+                .collect::<String>()
+        };
+        let foo = (pos(2, 12), pos(2, 15));
+        // The only range is in the middle of its line.
+        assert!(source_text(&[foo]).ends_with("\n    let x = foo();\n"));
+        // The range follows one on an earlier line.
+        assert!(source_text(&[(pos(1, 3), pos(1, 4)), foo])
+            .ends_with("\nfn a() {\n    let x = foo();\n"));
+    }
+
+    #[test]
+    fn invisible_groups() {
+        let invisible = |src: &str| -> TokenTree {
+            proc_macro2::Group::new(Delimiter::None, src.parse().unwrap()).into()
+        };
+        let render = |tokens: Vec<TokenTree>| -> String {
+            let ranges = super::generate_ranges(tokens.into_iter().collect(), &super::Spanr::new());
+            ranges
+                .generated
+                .iter()
+                .map(|chunk| chunk.text.as_str())
+                .collect()
+        };
+        let tokens = |src: &str| -> Vec<TokenTree> {
+            src.parse::<TokenStream>().unwrap().into_iter().collect()
+        };
+        // A comparison substituted into generic arguments.
+        let mut x = tokens("Foo<");
+        x.push(invisible("x > y"));
+        x.extend(tokens(">::new()"));
+        assert_eq!(render(x), "Foo<x > y>::new()");
+        // Jointly-spaced punctuation doesn't join across the boundary.
+        let minus = proc_macro2::Punct::new('-', proc_macro2::Spacing::Joint);
+        let inner: TokenStream = vec![tokens("x").remove(0), minus.into()]
+            .into_iter()
+            .collect();
+        let mut x = vec![proc_macro2::Group::new(Delimiter::None, inner).into()];
+        x.extend(tokens("> y"));
+        assert_eq!(render(x), "x - > y");
+        let mut x = tokens("let f =");
+        x.push(invisible("|x| x + 1"));
+        x.extend(tokens("; f(1)"));
+        assert_eq!(render(x), "let f = |x| x + 1;\nf(1)");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn raw_identifiers() {
+        let src = "struct r#Foo<T> { r#type: u8, r#match: T }";
+        assert_eq!(
+            pretty(src),
+            "struct r#Foo<T> {\n    r#type: u8, r#match: T\n}\n"
+        );
+
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        let (_, idx) = span_map
+            .generated()
+            .find(|(text, _)| *text == "r#type")
+            .unwrap();
+        let pos = |column| Position { line: 1, column };
+        assert_eq!(
+            span_map.resolve(idx.unwrap()),
+            Some(("foo.rs".into(), pos(18), pos(24)))
+        );
+        // Raw identifiers aren't keywords.
+        assert!(span_map
+            .ranges
+            .generated
+            .iter()
+            .filter(|chunk| chunk.text.starts_with("r#"))
+            .all(|chunk| chunk.highlight.is_none()));
+    }
+
+    #[test]
+    fn block_braces() {
+        let ranges = super::generate_ranges(
+            "fn foo() { if x { a } else { b } }".parse().unwrap(),
+            &super::Spanr::new(),
+        );
+        let braces: Vec<_> = ranges
+            .generated
+            .iter()
+            .filter_map(|chunk| Some((chunk.text.as_str(), chunk.block?)))
+            .collect();
+        assert_eq!(
+            braces,
+            [("{", 0), ("{", 1), ("}", 1), ("{", 2), ("}", 2), ("}", 0)]
+        );
+
+        let mut out = Vec::new();
+        super::write_html_parts(
+            &mut out,
+            &super::Spanr::new(),
+            &Ranges::default(),
+            vec![("{", vec![], None, Some(3))],
+            false,
+            &mut 1,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<div><span class=\"\" data-block=\"3\">{</span></div>"
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn search_box() {
+        let src = "fn foo() {}";
+        let html = |search| {
+            super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .search(search)
+                .build_html(src.parse().unwrap())
+                .unwrap()
+        };
+        assert!(html(true).contains(super::SEARCH_HTML));
+        assert!(!html(false).contains(super::SEARCH_HTML));
+        assert!(!html(false).contains("{SEARCH}"));
+        // Every placeholder of the default template is filled in.
+        assert!(!html(true).contains("{BOTTOM}"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn coalesced_chunks() {
+        let src = "{ { x } }";
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        // The indentation of `x` is written as one span, not one per level.
+        assert!(
+            html.contains("<div><span class=\"\">        </span><span class=\"c2 s0 \" data-source=\"0\" data-line=\"1\">x</span>")
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn minify() {
+        let src = "x += 1;";
+        // Give the `=` the span of the `+`, as `quote_spanned!` would.
+        let tokens = || -> TokenStream {
+            let mut x: Vec<TokenTree> = src.parse::<TokenStream>().unwrap().into_iter().collect();
+            let span = x[1].span();
+            x[2].set_span(span);
+            x.into_iter().collect()
+        };
+        let html = |minify| {
+            let html = super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .minify(minify)
+                .build_html(tokens())
+                .unwrap();
+            let start = html.find("<div id=\"left\">").unwrap();
+            let end = html[start..].find("</div>\n").unwrap();
+            html[start..start + end].to_string()
+        };
+        assert_eq!(
+            html(true),
+            "<div id=\"left\"><div><span class=\"c0 s0 \" data-source=\"0\" data-line=\"1\">x</span> \
+             <span class=\"c1 s0 \" data-source=\"0\" data-line=\"1\">+=</span> \
+             <span class=\"c2 s0 \" data-source=\"0\" data-line=\"1\">1</span>\
+             <span class=\"c3 s0 \" data-source=\"0\" data-line=\"1\">;</span></div><div></div>"
+        );
+        assert!(html(false).contains(
+            "<span class=\"\"> </span><span class=\"c1 s0 \" data-source=\"0\" data-line=\"1\">+=</span>\
+             <span class=\"\"> </span>"
+        ));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn multi_line_literals() {
+        let src = "fn f() {\n    let s = r#\"one\n  two\"#;\n}\n";
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .line_numbers(true)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        // The literal is split across rows at its line breaks, without being
+        // indented any further.
+        assert!(html.contains(
+            "<span class=\"c8 s0 \" data-source=\"0\" data-line=\"2\">r#\"one</span></div>\
+             <div><span class=\"ln\" data-line=\"3\"></span>\
+             <span class=\"c8 s0 \" data-source=\"0\" data-line=\"2\">  two\"#</span>\
+             <span class=\"c9 s0 \" data-source=\"0\" data-line=\"3\">;</span></div>"
+        ));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn line_highlight() {
+        let src = "fn foo() {}";
+        let html = |line_highlight| {
+            super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .line_highlight(line_highlight)
+                .build_html(src.parse().unwrap())
+                .unwrap()
+        };
+        assert!(html(true).contains("<body class=\"line-highlight\">"));
+        assert!(html(false).contains("<body class=\"\">"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn palette() {
+        let src = "x += 1;";
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .palette(["red", "blue"])
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        assert!(html.contains(
+            "<style>.c0 { background-color: red; }.c1 { background-color: blue; }\
+             .c2 { background-color: red; }"
+        ));
+    }
+
+    #[test]
+    fn invalid_palette() {
+        let src = "x += 1;";
+        let spanr = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .palette(["red", "red; } body { display: none"]);
+        assert!(matches!(
+            spanr.build_html(src.parse().unwrap()),
+            Err(super::SpanrError::InvalidColor(color)) if color == "red; } body { display: none"
+        ));
+        assert!(matches!(
+            spanr.build_html_fragment(src.parse().unwrap()),
+            Err(super::SpanrError::InvalidColor(_))
+        ));
+        assert!(matches!(
+            spanr.stream_html(src.parse().unwrap(), Vec::new()),
+            Err(super::SpanrError::InvalidColor(_))
+        ));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn stable_colors() {
+        let src = "a b c";
+        let css = |token_stream: TokenStream| {
+            let html = super::Spanr::new()
+                .parsed_source("foo.rs", src)
+                .hashed_classes(true)
+                .build_html(token_stream)
+                .unwrap();
+            let start = html.find("<style>.c").unwrap();
+            html[start..start + html[start..].find("</style>").unwrap()].to_string()
+        };
+        // Each span keeps its color when the generated code is reordered.
+        let tokens: Vec<TokenTree> = src.parse::<TokenStream>().unwrap().into_iter().collect();
+        assert_eq!(
+            css(tokens.iter().cloned().collect()),
+            css(tokens.iter().rev().cloned().collect())
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn labels() {
+        let src = "x += 1;";
+        let pos = |column| super::Position { line: 1, column };
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .label("foo.rs", pos(0), pos(1), "the \"target\"")
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        let span = "<span class=\"c0 s0 \" title=\"the &quot;target&quot;\"";
+        // Both the generated code and the source are labeled.
+        assert_eq!(html.matches(span).count(), 2);
+        assert_eq!(html.matches(" title=").count(), 2);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn layout() {
+        let src = "fn foo() {}";
+        let html = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .layout(super::Layout::Stacked)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        assert!(html.contains("<body class=\"line-highlight stacked\">"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn footer() {
+        let src = "fn foo() {}";
+        let html = |config: super::Spanr| {
+            config
+                .parsed_source("foo.rs", src)
+                .build_html(src.parse().unwrap())
+                .unwrap()
+        };
+        let footer = format!(
+            "<div id=\"footer\">spanr v{}</div>",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(html(super::Spanr::new()).contains(&footer));
+        assert!(!html(super::Spanr::new().footer(false)).contains("id=\"footer\""));
+        assert!(html(super::Spanr::new().timestamp(true)).contains(", generated 20"));
+
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(951_825_845);
+        assert_eq!(super::format_timestamp(time), "2000-02-29T12:04:05Z");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn html_fragment() {
+        let src = "fn foo() {}";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let fragment = config.build_html_fragment(src.parse().unwrap()).unwrap();
+        let html = config.build_html(src.parse().unwrap()).unwrap();
+        // The full report is composed of the same parts.
+        for part in [&fragment.left, &fragment.right, &fragment.script] {
+            assert!(html.contains(part.as_str()));
+        }
+        assert!(fragment
+            .left
+            .starts_with("<div id=\"left\"><div><span class=\"c0 s0 \" data-source=\"0\""));
+        assert!(fragment
+            .style
+            .ends_with("calc(var(--range-lightness) - 10%)); }"));
+        assert_eq!(fragment.features, "line-highlight");
+
+        // The rules laying out the panels don't depend on the page around them.
+        let fragment = config
+            .layout(super::Layout::Stacked)
+            .build_html_fragment(src.parse().unwrap())
+            .unwrap();
+        let selectors: Vec<_> = fragment
+            .style
+            .split('}')
+            .filter_map(|rule| rule.rsplit('{').nth(1))
+            .flat_map(|selectors| selectors.split(','))
+            .map(str::trim)
+            .collect();
+        for selector in &selectors {
+            let page = ["html", "body", "[data-theme=\"dark\"] body"];
+            assert!(
+                page.contains(selector) || !selector.contains("body"),
+                "`{}` only matches a full report",
+                selector
+            );
+        }
+        assert!(
+            selectors.contains(&"#left > div")
+                && fragment.left.starts_with("<div id=\"left\"><div>")
+        );
+        assert!(
+            selectors.contains(&"#right > div")
+                && fragment.right.starts_with("<div id=\"right\"><div")
+        );
+        assert_eq!(fragment.features, "line-highlight stacked");
+        assert!(selectors.contains(&".stacked"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn left_panel_html() {
+        let src = "fn foo() {}";
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let left = config.build_left_panel_html(src.parse().unwrap());
+        assert!(left.starts_with("<div><span class=\"c0 s0 \""));
+        let fragment = config.build_html_fragment(src.parse().unwrap()).unwrap();
+        assert_eq!(fragment.left, format!("<div id=\"left\">{}</div>", left));
+        // Without real spans, every token is unspanned.
+        assert!(!super::generate_left_panel_html(src.parse().unwrap()).contains("class=\"c"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn focus() {
+        let src = "x += 1;";
+        let html = |config: super::Spanr| {
+            config
+                .parsed_source("foo.rs", src)
+                .build_html(src.parse().unwrap())
+                .unwrap()
+        };
+        // `+=` is focused, in both panels.
+        let config = super::Spanr::new().focus_location("foo.rs:1:3").unwrap();
+        let html = html(config);
+        assert_eq!(html.matches(" focus ").count(), 2);
+        assert!(html.contains("<span class=\"c1 s0 focus \""));
+        assert!(matches!(
+            super::Spanr::new().focus_location("foo.rs:1"),
+            Err(super::SpanrError::InvalidLocation(_))
+        ));
+        assert!(super::Spanr::new().focus_location("foo.rs:0:1").is_err());
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn theme() {
+        let src = "fn foo() {}";
+        let html = |config: super::Spanr| {
+            config
+                .parsed_source("foo.rs", src)
+                .build_html(src.parse().unwrap())
+                .unwrap()
+        };
+        assert!(html(super::Spanr::new()).contains("<html data-theme=\"light\">"));
+        assert!(html(super::Spanr::new().theme(super::Theme::Dark))
+            .contains("<html data-theme=\"dark\">"));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn tab_width() {
+        let src = "fn foo() {\n\tlet x =\t1;\n}\n";
+        assert_ranges_cover_tokens("tab_width", src);
+        let (ranges, texts) = token_ranges("tab_width_expanded", src);
+        let config = super::Spanr::new().tab_width(4);
+        let (source_parts, _) = super::load_original_source(&ranges, &config);
+        let text: String = source_parts
+            .parts
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect();
+        assert!(text.ends_with("fn foo() {\n    let x = 1;\n}\n"));
+        for token in ["let", "x", "1"] {
+            let idx = texts.iter().position(|text| text == token).unwrap();
+            assert_eq!(covered_text(&source_parts, idx), token);
+        }
+    }
+
+    #[test]
+    fn custom_indent_unit() {
+        let x: TokenStream = "fn foo() { if x { 42 } }".parse().unwrap();
+        let span_map = super::Spanr::new().indent_unit("\t").build_span_map(x);
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "fn foo() {\n\tif x {\n\t\t42\n\t}\n}\n");
+        assert!(span_map
+            .generated()
+            .filter(|(text, _)| *text == "\t")
+            .all(|(_, idx)| idx.is_none()));
+    }
+
+    #[test]
+    fn no_real_spans() {
+        // Spans of a parsed string don't point into a file on disk.
+        let x: TokenStream = "fn foo() {}".parse().unwrap();
+        assert!(matches!(
+            super::generate_html(x),
+            Err(super::SpanrError::NoSpans)
+        ));
+    }
+
+    #[test]
+    fn unreadable_source() {
+        let mut ranges = Ranges::default();
+        let pos = Position { line: 1, column: 0 };
+        ranges.add_range("does/not/exist.rs".into(), pos, pos);
+        let config = super::Spanr::new();
+        let (source_parts, errors) = super::load_original_source(&ranges, &config);
+        assert!(source_parts
+            .parts
+            .iter()
+            .any(|(text, _)| text == "// <source unavailable>"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, std::path::Path::new("does/not/exist.rs"));
+
+        let mut out = Vec::new();
+        config
+            .write_report(ranges.clone(), |_, _| None, false, &mut out)
+            .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<div class=\"warning\">warning: failed to read does/not/exist.rs: "));
+
+        let res = config.write_report(ranges, |_, _| None, true, Vec::new());
+        assert!(matches!(
+            res,
+            Err(super::SpanrError::UnreadableSources(errors)) if errors.len() == 1
+        ));
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn multi_byte_columns() {
+        assert_ranges_cover_tokens(
+            "multi_byte_columns",
+            "fn foo() {\n    let s = \"🦀\"; bar(s, \"e\u{301}\", 'ß');\n}\n",
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn unicode_identifiers() {
+        let src = "fn café() {\n    let 変数 = naïve(Δx);\n}\n";
+        assert_ranges_cover_tokens("unicode_identifiers", src);
+
+        // The identifiers are highlighted in the source panel exactly, and
+        // the tokens after them on the same line are still lined up.
+        let config = super::Spanr::new().parsed_source("foo.rs", src);
+        let span_map = config.build_span_map(src.parse().unwrap());
+        let source_text = |ident: &str| -> String {
+            let idx = span_map
+                .generated()
+                .find(|(text, _)| *text == ident)
+                .and_then(|(_, idx)| idx)
+                .unwrap();
+            span_map
+                .source_parts()
+                .filter(|(_, indexes)| indexes.contains(&idx))
+                .map(|(text, _)| text)
+                .collect()
+        };
+        for ident in ["café", "変数", "naïve", "Δx", "(", ")", "{"] {
+            assert_eq!(source_text(ident), ident);
+        }
+        let html = config
+            .preserve_formatting(true)
+            .build_html(src.parse().unwrap())
+            .unwrap();
+        assert!(html.contains(">café</span>"));
+        assert!(html.contains(">naïve</span>"));
+
+        // A single capital letter is a type name, whatever its script.
+        assert_eq!(pretty("x = y as Δ<T>"), "x = y as Δ<T>");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn crlf_line_endings() {
+        let src = "fn foo() {\r\n    bar(1, 2);\r\n    baz\r\n}\r\n";
+        assert_ranges_cover_tokens("crlf_line_endings", src);
+        let (ranges, texts) = token_ranges("crlf_line_endings_parts", src);
+        let (source_parts, _) = super::load_original_source(&ranges, &super::Spanr::new());
+        assert!(source_parts
+            .parts
+            .iter()
+            .all(|(text, _)| !text.contains('\r')));
+        let baz = texts.iter().position(|text| text == "baz").unwrap();
+        let line_end = source_parts
+            .parts
+            .iter()
+            .position(|(_, indexes)| indexes.contains(&baz))
+            .unwrap();
+        // Nothing but the line break follows the last token on the line.
+        assert!(source_parts.parts[line_end + 1..]
+            .iter()
+            .take_while(|(text, _)| text != "\n")
+            .all(|(text, _)| text.is_empty()));
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let mut ranges = Ranges::default();
+        let pos = |line, column| Position { line, column };
+        let b1 = ranges.add_range("b.rs".into(), pos(2, 0), pos(2, 1));
+        let a = ranges.add_range("a.rs".into(), pos(5, 0), pos(5, 1));
+        let b0 = ranges.add_range("b.rs".into(), pos(1, 0), pos(1, 1));
+        for idx in [b1, a, b0] {
+            ranges.generated.push(super::Chunk {
+                text: "x".into(),
+                range: Some(idx),
+                highlight: None,
+                block: None,
+                layout: false,
+            });
+        }
+        ranges.sort();
+        assert_eq!(
+            ranges.source_list,
+            [PathBuf::from("a.rs"), PathBuf::from("b.rs")]
+        );
+        assert_eq!(
+            ranges
+                .range_list
+                .iter()
+                .map(|range| (range.source, range.start.line))
+                .collect::<Vec<_>>(),
+            [(0, 5), (1, 1), (1, 2)]
+        );
+        assert_eq!(
+            ranges
+                .generated
+                .iter()
+                .map(|chunk| chunk.range.unwrap())
+                .collect::<Vec<_>>(),
+            [2, 0, 1]
+        );
+        let source = ranges.sources[&PathBuf::from("b.rs")];
+        assert_eq!(
+            ranges.ranges[&super::Range {
+                source,
+                start: pos(1, 0),
+                end: pos(1, 1)
+            }],
+            1
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn context_lines() {
+        let src = "// one\n// two\nfn foo() {}\n// four\n// five\n// six\n";
+        let (mut ranges, _) = token_ranges("context_lines", "");
+        let path =
+            std::env::temp_dir().join(format!("spanr-{}-context_lines.rs", std::process::id()));
+        fs::write(&path, src).unwrap();
+        let pos = |line, column| Position { line, column };
+        ranges.add_range(path, pos(3, 3), pos(3, 6));
+        let rendered_with = |config: super::Spanr| -> String {
+            let (source_parts, _) = super::load_original_source(&ranges, &config);
+            let text: String = source_parts
+                .parts
+                .iter()
+                .map(|(text, _)| text.as_str())
+                .collect();
+            text.lines().skip(4).collect::<Vec<_>>().join("\n")
+        };
+        let rendered =
+            |context_lines| rendered_with(super::Spanr::new().context_lines(context_lines));
+        assert_eq!(rendered(0), "fn foo() {}");
+        assert_eq!(rendered(1), "// two\nfn foo() {}\n// four");
+        assert_eq!(rendered(5), src.trim_end());
+        assert_eq!(
+            rendered_with(super::Spanr::new().full_source(true)),
+            src.trim_end()
+        );
+    }
+
+    #[test]
+    fn generated_position_lookup() {
+        let mut ranges = Ranges::default();
+        let path = PathBuf::from("lib.rs");
+        let pos = |line, column| Position { line, column };
+        let foo = ranges.add_range(path.clone(), pos(3, 4), pos(3, 7));
+        let bar = ranges.add_range(path.clone(), pos(5, 0), pos(5, 3));
+        for (text, idx) in [
+            ("foo", Some(foo)),
+            (";", None),
+            ("\n", None),
+            ("bar", Some(bar)),
+        ] {
+            ranges.generated.push(super::Chunk {
+                text: text.into(),
+                range: idx,
+                highlight: None,
+                block: None,
+                layout: text == "\n",
+            });
+        }
+        let span_map = super::SpanMap {
+            ranges,
+            source_parts: SourceParts::default(),
+        };
+        let foo_location = Some((path.clone(), pos(3, 4), pos(3, 7)));
+        assert_eq!(span_map.source_at_generated(1, 0), foo_location);
+        assert_eq!(span_map.source_at_generated(1, 2), foo_location);
+        assert_eq!(span_map.source_at_generated(1, 3), None);
+        assert_eq!(
+            span_map.source_at_generated(2, 1),
+            Some((path, pos(5, 0), pos(5, 3)))
+        );
+        assert_eq!(span_map.source_at_generated(2, 3), None);
+        assert_eq!(span_map.source_at_generated(3, 0), None);
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn doc_comments() {
+        let src = "//! Inner\n/// Hello\n/// World\nfn foo() {}";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(
+            generated,
+            "//! Inner\n/// Hello\n/// World\nfn foo() {\n}\n"
+        );
+        let (_, idx) = span_map
+            .generated()
+            .find(|(text, _)| *text == "/// Hello")
+            .unwrap();
+        let pos = |line, column| Position { line, column };
+        assert_eq!(
+            span_map.resolve(idx.unwrap()),
+            Some(("foo.rs".into(), pos(2, 0), pos(2, 9)))
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn parsed_source() {
+        let src = "fn foo() {}";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = super::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        assert_eq!(
+            span_map.source_path(0),
+            Some(std::path::Path::new("foo.rs"))
+        );
+        let (text, idx) = span_map.generated().next().unwrap();
+        assert_eq!(text, "fn");
+        let pos = |line, column| Position { line, column };
+        assert_eq!(
+            span_map.resolve(idx.unwrap()),
+            Some(("foo.rs".into(), pos(1, 0), pos(1, 2)))
+        );
+        let source_text: String = span_map.source_parts().map(|(text, _)| text).collect();
+        assert!(source_text.ends_with("fn foo() {}\n"));
+    }
+
+    #[cfg(feature = "syn")]
+    #[test]
+    fn syn_nodes() {
+        let file: syn::File = syn::parse_str("fn foo() {}").unwrap();
+        assert!(matches!(
+            super::generate_html_from_syn(&file),
+            Err(super::SpanrError::NoSpans)
+        ));
+        assert!(matches!(
+            super::generate_html_from_syn(&file.items[0]),
+            Err(super::SpanrError::NoSpans)
+        ));
+    }
+
+    #[test]
+    fn template_missing_placeholder() {
+        let x: TokenStream = "foo".parse().unwrap();
+        let res = super::Spanr::new()
+            .template("<html>{LEFT}</html>")
+            .build_html(x);
+        assert!(matches!(
+            res,
+            Err(super::SpanrError::MissingPlaceholder("RIGHT"))
+        ));
+    }
+
+    #[test]
+    fn source_classes() {
+        let mut ranges = Ranges::default();
+        let pos = Position { line: 1, column: 0 };
+        let a = ranges.add_range("a.rs".into(), pos, pos);
+        let b = ranges.add_range("b.rs".into(), pos, pos);
+        let mut out = Vec::new();
+        super::write_html_parts(
+            &mut out,
+            &super::Spanr::new(),
+            &ranges,
+            vec![
+                ("x", vec![a], None, None),
+                ("y", vec![b], None, None),
+                ("\n", vec![], None, None),
+                ("z", vec![], None, None),
+            ],
+            false,
+            &mut 1,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<div><span class=\"c0 s0 \">x</span><span class=\"c1 s1 \">y</span>\
+             </div><div><span class=\"\">z</span></div>"
+        );
+    }
+
+    #[test]
+    fn indent_padding() {
+        let parts = || {
+            vec![
+                ("    ", vec![], None, None),
+                ("    ", vec![], None, None),
+                ("x", vec![], None, None),
+                ("\n", vec![], None, None),
+                ("\t", vec![], None, None),
+                ("\n", vec![], None, None),
+                ("y", vec![], None, None),
+            ]
+        };
+        let write = |indent_padding| {
+            let mut out = Vec::new();
+            super::write_html_parts(
+                &mut out,
+                &super::Spanr::new().indent_padding(indent_padding),
+                &Ranges::default(),
+                parts(),
+                true,
+                &mut 1,
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            write(false),
+            "<div><span class=\"\">        x</span></div><div><span class=\"\">\t</span></div>\
+             <div><span class=\"\">y</span></div>"
+        );
+        assert_eq!(
+            write(true),
+            "<div style=\"--indent: 8ch\"><span class=\"\">x</span></div>\
+             <div style=\"--indent: 4ch\"></div><div><span class=\"\">y</span></div>"
+        );
+    }
+
+    #[test]
+    fn hashed_classes() {
+        let class_of = |paths: &[&str], column| {
+            let mut ranges = Ranges::default();
+            let pos = Position { line: 1, column };
+            for path in paths {
+                ranges.add_range(path.into(), pos, pos);
+            }
+            let idx = ranges.add_range("a.rs".into(), pos, pos);
+            let mut out = Vec::new();
+            super::write_html_parts(
+                &mut out,
+                &super::Spanr::new().hashed_classes(true),
+                &ranges,
+                vec![("x", vec![idx], None, None)],
+                false,
+                &mut 1,
+            )
+            .unwrap();
+            let html = String::from_utf8(out).unwrap();
+            html.split('"')
+                .nth(1)
+                .unwrap()
+                .split(' ')
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        let class = class_of(&[], 0);
+        assert_eq!(class.len(), 17);
+        assert!(class.starts_with('c'));
+        // Other ranges don't affect the class, but the extent does.
+        assert_eq!(class, class_of(&["b.rs", "c.rs"], 0));
+        assert_ne!(class, class_of(&[], 1));
+    }
+
+    #[test]
+    fn line_numbers() {
+        let mut out = Vec::new();
+        super::write_html_parts(
+            &mut out,
+            &super::Spanr::new().line_numbers(true),
+            &Ranges::default(),
+            vec![
+                ("x", vec![], None, None),
+                ("\n", vec![], None, None),
+                ("y", vec![], None, None),
+            ],
+            false,
+            &mut 1,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<div><span class=\"ln\" data-line=\"1\"></span><span class=\"\">x</span></div>\
+             <div><span class=\"ln\" data-line=\"2\"></span><span class=\"\">y</span></div>"
+        );
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let mut out = Vec::new();
+        super::write_template(&mut out, "a {X} {b} {Y}{ {Z}", |out, name| match name {
+            "X" | "Y" => {
+                out.extend_from_slice(name.to_lowercase().as_bytes());
+                Ok(true)
+            }
+            _ => Ok(false),
+        })
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a x {b} y{ {Z}");
+    }
 }
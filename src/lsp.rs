@@ -0,0 +1,201 @@
+//! A representation of the span mapping as [Language Server Protocol]
+//! locations, for editor plugins.
+//!
+//! Unlike the rest of the crate, lines are 0-based, as in LSP. Characters are
+//! counted in `char`s, which corresponds to the `utf-32` position encoding
+//! rather than the default `utf-16` one, so are only exact for other
+//! encodings when the code is ASCII.
+//!
+//! [Language Server Protocol]: https://microsoft.github.io/language-server-protocol/
+
+use std::fmt::Write;
+use std::path::Path;
+
+use proc_macro2::TokenStream;
+
+use crate::{build_span_map, SpanMap};
+
+/// A position in a document, with a 0-based line and character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+impl From<crate::Position> for Position {
+    fn from(other: crate::Position) -> Self {
+        Self {
+            line: other.line.saturating_sub(1),
+            character: other.column,
+        }
+    }
+}
+
+/// A region of a document, from `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A region of a source file, identified by its `file://` URI.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
+
+/// A chunk of the pretty-printed generated code, along with the source
+/// location it was spanned to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MappedLocation {
+    /// Where the chunk is in the generated code.
+    pub generated: Range,
+    /// Where the chunk was spanned to.
+    pub source: Location,
+}
+
+/// Returns the `file://` URI of `path`, percent-encoding any characters not
+/// allowed in a URI path.
+///
+/// Relative paths, such as those given to code parsed from a string, can't be
+/// made into a proper URI, so are encoded as they are.
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut res = String::from("file://");
+    // Windows paths start with a drive letter rather than a `/`.
+    if path.as_bytes().get(1) == Some(&b':') {
+        res.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                res.push(byte as char)
+            }
+            _ => write!(res, "%{:02X}", byte).unwrap(),
+        }
+    }
+    res
+}
+
+impl SpanMap {
+    /// Lists each spanned chunk of the pretty-printed generated code, along
+    /// with the source location it was spanned to.
+    ///
+    /// Consecutive chunks spanned to the same range are listed as one.
+    pub fn to_lsp_locations(&self) -> Vec<MappedLocation> {
+        let mut res: Vec<MappedLocation> = Vec::new();
+        let mut pos = Position {
+            line: 0,
+            character: 0,
+        };
+        let mut last = None;
+        for (text, idx) in self.generated() {
+            if text == "\n" {
+                pos.line += 1;
+                pos.character = 0;
+                last = None;
+                continue;
+            }
+            let start = pos;
+            // Tokens spanning several lines, such as multi-line strings, end
+            // on a later line.
+            match text.rsplit_once('\n') {
+                Some((before, last)) => {
+                    pos.line += before.matches('\n').count() + 1;
+                    pos.character = last.chars().count();
+                }
+                None => pos.character += text.chars().count(),
+            }
+            let (idx, range) = match idx.and_then(|idx| Some((idx, self.range(idx)?))) {
+                Some(res) => res,
+                None => {
+                    last = None;
+                    continue;
+                }
+            };
+            if last == Some(idx) {
+                if let Some(location) = res.last_mut() {
+                    location.generated.end = pos;
+                    continue;
+                }
+            }
+            last = Some(idx);
+            res.push(MappedLocation {
+                generated: Range { start, end: pos },
+                source: Location {
+                    uri: file_uri(&self.ranges.source_list[range.source]),
+                    range: Range {
+                        start: range.start.into(),
+                        end: range.end.into(),
+                    },
+                },
+            });
+        }
+        res
+    }
+}
+
+/// Lists each spanned chunk of the pretty-printed generated code of a token
+/// stream, along with the source location it was spanned to.
+pub fn to_lsp_locations(token_stream: TokenStream) -> Vec<MappedLocation> {
+    build_span_map(token_stream).to_lsp_locations()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[cfg(feature = "span-locations")]
+    use proc_macro2::TokenStream;
+
+    #[cfg(feature = "span-locations")]
+    use super::{Position, Range};
+
+    #[test]
+    fn file_uri() {
+        assert_eq!(
+            super::file_uri(Path::new("/a b/c.rs")),
+            "file:///a%20b/c.rs"
+        );
+        assert_eq!(
+            super::file_uri(Path::new("C:\\src\\lib.rs")),
+            "file:///C:/src/lib.rs"
+        );
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn lsp_locations() {
+        let src = "fn foo() {\n    bar\n}";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = crate::Spanr::new()
+            .parsed_source("/nonexistent/foo.rs", src)
+            .build_span_map(x);
+        let locations = span_map.to_lsp_locations();
+        let range = |line, start, end| Range {
+            start: Position {
+                line,
+                character: start,
+            },
+            end: Position {
+                line,
+                character: end,
+            },
+        };
+        // `bar` is on the second line, indented the same in both.
+        let bar = locations
+            .iter()
+            .find(|location| location.generated.start.line == 1)
+            .unwrap();
+        assert_eq!(bar.generated, range(1, 4, 7));
+        assert_eq!(bar.source.range, range(1, 4, 7));
+        assert_eq!(bar.source.uri, "file:///nonexistent/foo.rs");
+        assert_eq!(locations[0].generated, range(0, 0, 2));
+        assert_eq!(locations.len(), 7);
+    }
+}
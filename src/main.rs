@@ -0,0 +1,116 @@
+//! Reads Rust code, such as the output of `cargo expand`, and renders a
+//! report mapping it back to itself.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+
+use proc_macro2::TokenStream;
+
+const USAGE: &str = "\
+usage: spanr [FILE] (-o OUTPUT | --stdout) [--focus LOCATION]...
+
+Reads Rust code from FILE, or from stdin if FILE is omitted or `-`, and
+renders an HTML report of its spans.
+
+options:
+    -o OUTPUT   write the report to OUTPUT
+    --stdout    write the report to stdout
+    --focus LOCATION
+                draw attention to the code at LOCATION, given as
+                `FILE:LINE:COLUMN`
+    -h, --help  print this message";
+
+enum Output {
+    File(PathBuf),
+    Stdout,
+}
+
+struct Args {
+    input: Option<PathBuf>,
+    output: Output,
+    focus: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut focus = Vec::new();
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-h") | Some("--help") => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            Some("-o") => {
+                let path = args.next().ok_or("`-o` requires an argument")?;
+                output = Some(Output::File(path.into()));
+            }
+            Some("--stdout") => output = Some(Output::Stdout),
+            Some("--focus") => {
+                let location = args.next().ok_or("`--focus` requires an argument")?;
+                let location = location
+                    .into_string()
+                    .map_err(|_| "`--focus` requires a UTF-8 argument")?;
+                focus.push(location);
+            }
+            Some("-") => input = None,
+            Some(flag) if flag.starts_with('-') => {
+                return Err(format!("unknown option `{}`", flag));
+            }
+            _ if input.is_some() => return Err("more than one input file given".into()),
+            _ => input = Some(arg.into()),
+        }
+    }
+    let output = output.ok_or("one of `-o` or `--stdout` is required")?;
+    Ok(Args {
+        input,
+        output,
+        focus,
+    })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let (path, code) = match &args.input {
+        Some(path) => {
+            let code = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read `{}`: {}", path.display(), e))?;
+            (path.clone(), code)
+        }
+        None => {
+            let mut code = String::new();
+            io::stdin()
+                .read_to_string(&mut code)
+                .map_err(|e| format!("failed to read stdin: {}", e))?;
+            (PathBuf::from("<stdin>"), code)
+        }
+    };
+    let token_stream: TokenStream = code
+        .parse()
+        .map_err(|e| format!("failed to parse `{}`: {}", path.display(), e))?;
+    let mut spanr = spanr::Spanr::new().parsed_source(path, code);
+    for location in &args.focus {
+        spanr = spanr.focus_location(location).map_err(|e| e.to_string())?;
+    }
+    let res = match &args.output {
+        Output::File(output) => spanr.save(token_stream, output),
+        Output::Stdout => spanr.write_html(token_stream, io::stdout().lock()),
+    };
+    res.map_err(|e| e.to_string())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,95 @@
+//! A Markdown rendering of the span mapping, for pasting where HTML won't
+//! render, such as into issue trackers.
+
+use proc_macro2::TokenStream;
+
+use crate::{build_span_map, SpanMap};
+
+/// Returns a code fence longer than any run of backticks in `text`.
+fn fence_for(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+impl SpanMap {
+    /// Renders the generated code as a fenced Rust code block, followed by a
+    /// list of the source ranges it was spanned to.
+    ///
+    /// Each run of generated code spanned to a range is followed by a `«n»`
+    /// marker giving the index of the range, which refers to the list below,
+    /// and can't be mistaken for Rust. Locations in the list are given as
+    /// `path:line:column-line:column`, counting columns from 1 as compiler
+    /// diagnostics do.
+    pub fn to_markdown(&self) -> String {
+        let chunks: Vec<_> = self.generated().collect();
+        let mut code = String::new();
+        for (i, &(text, idx)) in chunks.iter().enumerate() {
+            code.push_str(text);
+            let idx = match idx {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let next = chunks[i + 1..]
+                .iter()
+                .find(|(text, idx)| idx.is_some() || !text.trim().is_empty());
+            if next.is_none_or(|&(_, next)| next != Some(idx)) {
+                code.push_str(&format!("«{}»", idx));
+            }
+        }
+
+        let fence = fence_for(&code);
+        let mut res = format!("{}rust\n{}", fence, code);
+        if !code.ends_with('\n') {
+            res.push('\n');
+        }
+        res.push_str(&fence);
+        res.push_str("\n\n");
+        for (idx, range) in self.ranges() {
+            let path = &self.ranges.source_list[range.source];
+            res.push_str(&format!(
+                "- `«{}»` `{}:{}:{}-{}:{}`\n",
+                idx,
+                path.display(),
+                range.start.line,
+                range.start.column + 1,
+                range.end.line,
+                range.end.column + 1
+            ));
+        }
+        res
+    }
+}
+
+/// Renders the span mapping of a token stream as Markdown, with the
+/// generated code in a fenced code block followed by a list of the source
+/// ranges it was spanned to.
+pub fn to_markdown(token_stream: TokenStream) -> String {
+    build_span_map(token_stream).to_markdown()
+}
+
+#[cfg(all(test, feature = "span-locations"))]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn markdown() {
+        let src = "x += 1;";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        assert_eq!(
+            span_map.to_markdown(),
+            "```rust\n\
+             x«0» +«1»=«2» 1«3»;«4»\n\
+             ```\n\
+             \n\
+             - `«0»` `foo.rs:1:1-1:2`\n\
+             - `«1»` `foo.rs:1:3-1:4`\n\
+             - `«2»` `foo.rs:1:4-1:5`\n\
+             - `«3»` `foo.rs:1:6-1:7`\n\
+             - `«4»` `foo.rs:1:7-1:8`\n"
+        );
+        assert_eq!(super::fence_for("a ```` b"), "`````");
+    }
+}
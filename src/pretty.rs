@@ -0,0 +1,279 @@
+//! Formatting of the generated code with prettyplease, for output matching
+//! what rustfmt would produce.
+//!
+//! prettyplease prints a syntax tree without its spans, so the formatted code
+//! is lexed again and its tokens paired back up with the tokens they were
+//! printed from.
+
+use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
+
+use crate::visitor::{TokenVisitor, KEYWORDS};
+use crate::{Highlight, TokenKind};
+
+/// How far ahead to look for a matching token, when the tokens of the
+/// formatted code don't line up with the original ones.
+const LOOKAHEAD: usize = 8;
+
+/// A token, with groups split into their delimiters.
+struct Leaf {
+    text: String,
+    span: Span,
+    kind: TokenKind,
+    highlight: Option<Highlight>,
+}
+
+/// Flattens a token stream into its tokens, in order. The contents of
+/// invisible groups are included without any delimiters.
+fn leaves(token_stream: TokenStream, res: &mut Vec<Leaf>) {
+    let mut after_lifetime_quote = false;
+    for token_tree in token_stream {
+        let leaf = |text: &str, span, kind, highlight| Leaf {
+            text: text.into(),
+            span,
+            kind,
+            highlight,
+        };
+        match token_tree {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                if !open.is_empty() {
+                    res.push(leaf(open, group.span_open(), TokenKind::Group, None));
+                }
+                leaves(group.stream(), res);
+                if !close.is_empty() {
+                    res.push(leaf(close, group.span_close(), TokenKind::Group, None));
+                }
+            }
+            TokenTree::Ident(ident) => {
+                let text = ident.to_string();
+                let highlight = if after_lifetime_quote {
+                    Some(Highlight::Lifetime)
+                } else if KEYWORDS.contains(&text.as_str()) {
+                    Some(Highlight::Keyword)
+                } else {
+                    None
+                };
+                res.push(leaf(&text, ident.span(), TokenKind::Ident, highlight));
+            }
+            TokenTree::Literal(literal) => {
+                let text = literal.to_string();
+                let highlight = Some(Highlight::of_literal(&text));
+                res.push(leaf(&text, literal.span(), TokenKind::Literal, highlight));
+            }
+            TokenTree::Punct(punct) => {
+                let is_quote = punct.as_char() == '\'';
+                let highlight = is_quote.then_some(Highlight::Lifetime);
+                let text = punct.as_char().to_string();
+                res.push(leaf(&text, punct.span(), TokenKind::Punct, highlight));
+                after_lifetime_quote = is_quote;
+                continue;
+            }
+        }
+        after_lifetime_quote = false;
+    }
+}
+
+/// Pairs each token of `formatted` with the token of `original` it was
+/// printed from, where their text matches.
+///
+/// prettyplease only adds or drops the odd token, such as a trailing comma,
+/// so a short lookahead is enough to get the two back in step.
+fn align(original: &[Leaf], formatted: &[Leaf]) -> Vec<Option<usize>> {
+    let mut res = vec![None; formatted.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < original.len() && j < formatted.len() {
+        if original[i].text == formatted[j].text {
+            res[j] = Some(i);
+            i += 1;
+            j += 1;
+            continue;
+        }
+        // Skip ahead to the nearest pair of matching tokens.
+        let next = (1..=LOOKAHEAD * 2)
+            .flat_map(|dist| (0..=dist).map(move |di| (di, dist - di)))
+            .find(|&(di, dj)| {
+                let pair = original.get(i + di).zip(formatted.get(j + dj));
+                pair.is_some_and(|(a, b)| a.text == b.text)
+            });
+        let (di, dj) = next.unwrap_or((1, 1));
+        i += di;
+        j += dj;
+    }
+    res
+}
+
+/// Returns the byte offset of the start of each line of `text`.
+fn line_offsets(text: &str) -> Vec<usize> {
+    let mut res = vec![0];
+    res.extend(text.match_indices('\n').map(|(offset, _)| offset + 1));
+    res
+}
+
+/// Returns the byte offset in `text` of a position reported by a span, given
+/// the offsets of its lines.
+fn offset_of(text: &str, lines: &[usize], line: usize, column: usize) -> Option<usize> {
+    let start = *lines.get(line.checked_sub(1)?)?;
+    let line_text = text[start..].split('\n').next()?;
+    let column_offset = match line_text.char_indices().nth(column) {
+        Some((offset, _)) => offset,
+        None if column == line_text.chars().count() => line_text.len(),
+        None => return None,
+    };
+    Some(start + column_offset)
+}
+
+/// Formatted code, along with the byte range of each of its tokens and the
+/// original token each was printed from, if any.
+type Formatted = (String, Vec<(usize, usize, Option<Leaf>)>);
+
+/// Formats `token_stream` with prettyplease.
+///
+/// Returns `None` if the token stream doesn't parse as a file, or if the
+/// locations of the formatted tokens can't be found.
+fn format(token_stream: TokenStream) -> Option<Formatted> {
+    let file: syn::File = syn::parse2(token_stream.clone()).ok()?;
+    let formatted = prettyplease::unparse(&file);
+    let mut original = Vec::new();
+    leaves(token_stream, &mut original);
+    let mut printed = Vec::new();
+    leaves(formatted.parse().ok()?, &mut printed);
+
+    let lines = line_offsets(&formatted);
+    let alignment = align(&original, &printed);
+    let mut original: Vec<_> = original.into_iter().map(Some).collect();
+    let mut res = Vec::with_capacity(printed.len());
+    for (leaf, idx) in printed.iter().zip(alignment) {
+        let (start, end) = (leaf.span.start(), leaf.span.end());
+        let start = offset_of(&formatted, &lines, start.line, start.column)?;
+        let end = offset_of(&formatted, &lines, end.line, end.column)?;
+        // Doc comments are lexed into several tokens spanning the comment,
+        // but every other token should be found where it was printed.
+        if start > end
+            || (!formatted[start..end].starts_with("//") && formatted[start..end] != leaf.text)
+        {
+            return None;
+        }
+        res.push((start, end, idx.and_then(|idx| original[idx].take())));
+    }
+    Some((formatted, res))
+}
+
+impl TokenVisitor<'_> {
+    /// Writes out a token stream as formatted by prettyplease, returning
+    /// whether it could be formatted.
+    ///
+    /// Tokens keep the spans of the tokens they were printed from, while
+    /// those prettyplease added, such as trailing commas, have none.
+    pub(crate) fn visit_formatted(&mut self, token_stream: TokenStream) -> bool {
+        let (formatted, tokens) = match format(token_stream) {
+            Some(res) => res,
+            None => return false,
+        };
+        let layout = |visitor: &mut Self, text: &str| {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    visitor.add_str("\n");
+                }
+                if !line.is_empty() {
+                    visitor.add_str(line);
+                }
+            }
+        };
+        let mut offset = 0;
+        for (start, end, leaf) in tokens {
+            let range_idx = leaf
+                .as_ref()
+                .and_then(|leaf| self.add_span(leaf.span, leaf.kind));
+            if start < offset {
+                // The rest of a doc comment, which is spanned to its literal.
+                let is_literal = leaf
+                    .as_ref()
+                    .is_some_and(|leaf| leaf.highlight == Some(Highlight::Str));
+                if is_literal && range_idx.is_some() {
+                    if let Some(chunk) = self.ranges.generated.last_mut() {
+                        chunk.range = range_idx;
+                    }
+                }
+                continue;
+            }
+            layout(self, &formatted[offset..start]);
+            let text = &formatted[start..end];
+            let highlight = leaf.as_ref().and_then(|leaf| leaf.highlight);
+            self.add_token(text, range_idx, highlight);
+            let block = match text {
+                "{" => {
+                    self.blocks.push(self.block_count);
+                    self.block_count += 1;
+                    self.blocks.last().copied()
+                }
+                "}" => self.blocks.pop(),
+                _ => None,
+            };
+            if let Some(chunk) = self.ranges.generated.last_mut() {
+                chunk.block = block;
+            }
+            offset = end;
+        }
+        layout(self, &formatted[offset..]);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    fn span_map(src: &str) -> crate::SpanMap {
+        crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .prettyplease(true)
+            .build_span_map(src.parse::<TokenStream>().unwrap())
+    }
+
+    #[test]
+    fn formatted() {
+        let span_map = span_map("fn foo(a:u8,b:u8)->u8{a+b}");
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "fn foo(a: u8, b: u8) -> u8 {\n    a + b\n}\n");
+        let spanned: Vec<_> = span_map
+            .generated()
+            .filter_map(|(text, idx)| Some((text, span_map.range(idx?)?.start.column)))
+            .collect();
+        assert!(spanned.contains(&("b", 12)));
+        assert!(spanned.contains(&("+", 23)));
+    }
+
+    #[test]
+    fn added_tokens() {
+        let span_map = span_map("fn f() { match x { A => 1, B => 2 } }");
+        let synthetic: Vec<_> = span_map
+            .synthetic_regions()
+            .into_iter()
+            .map(|(_, _, text)| text)
+            .collect();
+        // The trailing comma of the last arm is added by prettyplease.
+        assert_eq!(synthetic, [","]);
+    }
+
+    #[test]
+    fn fallback() {
+        let span_map = span_map("x += 1;");
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "x += 1;\n");
+    }
+
+    #[test]
+    fn doc_comments() {
+        let span_map = span_map("/// Docs\nstruct Foo;");
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "/// Docs\nstruct Foo;\n");
+        let (text, idx) = span_map.generated().next().unwrap();
+        assert_eq!(text, "/// Docs");
+        assert!(idx.is_some());
+    }
+}
@@ -0,0 +1,391 @@
+//! Writing of the panels of a report as HTML.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use html_escape::{encode_double_quoted_attribute, encode_text};
+
+use crate::{Ranges, Spanr};
+
+/// Writes a panel of chunks, each tagged with a `c{idx}` class for every
+/// range covering it, and an `s{source}` class for every source those ranges
+/// belong to.
+///
+/// Each chunk may also have an extra class, and the index of the block it
+/// opens or closes, which is written as a `data-block` attribute.
+///
+/// Consecutive chunks with the same ranges and class share a span, and if
+/// minifying, whitespace outside of any range is written without one.
+///
+/// The `generated` panel is written with the attributes clicking a chunk
+/// needs to jump to its source, and if configured, with whitespace outside of
+/// any range that starts a row written as the width of the row's indentation
+/// instead.
+///
+/// Rows are numbered from `line`, which is advanced past the rows written.
+pub(crate) fn write_html_parts<'a, T: IntoIterator<Item = usize>>(
+    out: &mut impl Write,
+    config: &Spanr,
+    ranges: &Ranges,
+    parts: impl IntoIterator<Item = (&'a str, T, Option<&'static str>, Option<usize>)>,
+    generated: bool,
+    line: &mut usize,
+) -> io::Result<()> {
+    let indent_padding = generated && config.indent_padding;
+    let mut start_row = |out: &mut dyn Write, indent: usize| {
+        if indent > 0 {
+            write!(out, "<div style=\"--indent: {}ch\">", indent)?;
+        } else {
+            out.write_all(b"<div>")?;
+        }
+        if config.line_numbers {
+            write!(out, "<span class=\"ln\" data-line=\"{}\"></span>", line)?;
+            *line += 1;
+        }
+        Ok::<_, io::Error>(())
+    };
+    // Parts are held back to be merged with those following them that are
+    // covered by the same ranges.
+    type Pending = Option<(String, Vec<usize>, Option<&'static str>)>;
+    let write_pending = |out: &mut dyn Write, pending: &mut Pending| match pending.take() {
+        // Whitespace outside of any range needs no span of its own.
+        Some((text, indexes, None))
+            if config.minify && indexes.is_empty() && text.trim().is_empty() =>
+        {
+            out.write_all(encode_text(&text).as_bytes())
+        }
+        Some((text, indexes, class)) => {
+            let attrs = SpanAttrs {
+                class,
+                block: None,
+                jump: generated,
+            };
+            write_html_span(out, config, ranges, &text, &indexes, attrs)
+        }
+        None => Ok(()),
+    };
+    let mut pending: Pending = None;
+    // The width of the indentation of the current row, until anything else
+    // is written on it.
+    let mut row_indent = Some(0);
+    for (text, indexes, class, block) in parts {
+        let indexes: Vec<usize> = indexes.into_iter().collect();
+        // A token spanning several lines, such as a multi-line string, is
+        // split across rows, keeping its line breaks.
+        let mut lines = Vec::new();
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push("\n");
+            }
+            if !line.is_empty() || text.is_empty() {
+                lines.push(line);
+            }
+        }
+        for text in lines {
+            if let Some(indent) = &mut row_indent {
+                let is_indent = !text.is_empty() && text.chars().all(|c| c == ' ' || c == '\t');
+                if indent_padding && is_indent && indexes.is_empty() && class.is_none() {
+                    // Tabs are as wide as the `tab-size` of the panels.
+                    *indent += text
+                        .chars()
+                        .map(|c| if c == '\t' { 4 } else { 1 })
+                        .sum::<usize>();
+                    continue;
+                }
+                start_row(out, *indent)?;
+                row_indent = None;
+            }
+            if text != "\n" && block.is_none() {
+                if let Some((pending_text, pending_indexes, pending_class)) = &mut pending {
+                    if *pending_indexes == indexes && *pending_class == class {
+                        pending_text.push_str(text);
+                        continue;
+                    }
+                }
+                write_pending(out, &mut pending)?;
+                pending = Some((text.into(), indexes.clone(), class));
+                continue;
+            }
+            write_pending(out, &mut pending)?;
+            if text == "\n" {
+                out.write_all(b"</div>")?;
+                row_indent = Some(0);
+            } else {
+                let attrs = SpanAttrs {
+                    class,
+                    block,
+                    jump: generated,
+                };
+                write_html_span(out, config, ranges, text, &indexes, attrs)?;
+            }
+        }
+    }
+    if let Some(indent) = row_indent {
+        start_row(out, indent)?;
+    }
+    write_pending(out, &mut pending)?;
+    out.write_all(b"</div>")
+}
+
+/// Writes the rows of the source panel, with a span for each range nested
+/// within the spans of the ranges containing it, as far as the row goes.
+///
+/// Each part may have an extra class, which is given to a span of its own.
+/// A part covered by ranges that cross each other rather than nest is written
+/// as a single span tagged with all of them, as in [`write_html_parts`].
+///
+/// Parts never contain line breaks, apart from the parts that are just a
+/// line break, which end the row.
+pub(crate) fn write_nested_parts<'a>(
+    out: &mut impl Write,
+    config: &Spanr,
+    ranges: &Ranges,
+    parts: impl IntoIterator<Item = (&'a str, &'a [usize], Option<&'static str>)>,
+) -> io::Result<()> {
+    let mut line = 1;
+    let mut start_row = |out: &mut dyn Write| {
+        out.write_all(b"<div>")?;
+        if config.line_numbers {
+            write!(out, "<span class=\"ln\" data-line=\"{}\"></span>", line)?;
+            line += 1;
+        }
+        Ok::<_, io::Error>(())
+    };
+    let no_attrs = || SpanAttrs {
+        class: None,
+        block: None,
+        jump: false,
+    };
+    // Text outside of any range is held back to be merged with the text
+    // following it, when that has the same class.
+    type Pending = Option<(String, Option<&'static str>)>;
+    let write_pending = |out: &mut dyn Write, pending: &mut Pending| match pending.take() {
+        Some((text, None)) if config.minify && text.trim().is_empty() => {
+            out.write_all(encode_text(&text).as_bytes())
+        }
+        Some((text, class)) => write_html_span(
+            out,
+            config,
+            ranges,
+            &text,
+            &[],
+            SpanAttrs {
+                class,
+                ..no_attrs()
+            },
+        ),
+        None => Ok(()),
+    };
+    let close = |out: &mut dyn Write, open: &mut Vec<usize>, len: usize| {
+        for _ in len..open.len() {
+            out.write_all(b"</span>")?;
+        }
+        open.truncate(len);
+        Ok::<_, io::Error>(())
+    };
+    let mut pending: Pending = None;
+    // The ranges of the spans currently open, from the outermost.
+    let mut open = Vec::new();
+    start_row(out)?;
+    for (text, indexes, class) in parts {
+        if text == "\n" {
+            write_pending(out, &mut pending)?;
+            close(out, &mut open, 0)?;
+            out.write_all(b"</div>")?;
+            start_row(out)?;
+            continue;
+        }
+        let range = |idx: usize| &ranges.range_list[idx];
+        let mut nested = indexes.to_vec();
+        nested.sort_by(|&a, &b| range(a).cmp_nesting(range(b)));
+        if !nested.windows(2).all(|w| range(w[0]).contains(range(w[1]))) {
+            write_pending(out, &mut pending)?;
+            close(out, &mut open, 0)?;
+            let attrs = SpanAttrs {
+                class,
+                ..no_attrs()
+            };
+            write_html_span(out, config, ranges, text, indexes, attrs)?;
+            continue;
+        }
+        let common = open.iter().zip(&nested).take_while(|(a, b)| a == b).count();
+        if nested.is_empty() {
+            close(out, &mut open, 0)?;
+            match &mut pending {
+                Some((pending_text, pending_class)) if *pending_class == class => {
+                    pending_text.push_str(text);
+                }
+                _ => {
+                    write_pending(out, &mut pending)?;
+                    pending = Some((text.into(), class));
+                }
+            }
+            continue;
+        }
+        write_pending(out, &mut pending)?;
+        close(out, &mut open, common)?;
+        for &idx in &nested[common..] {
+            write_span_start(out, config, ranges, &[idx], no_attrs())?;
+            open.push(idx);
+        }
+        if class.is_some() {
+            let attrs = SpanAttrs {
+                class,
+                ..no_attrs()
+            };
+            write_html_span(out, config, ranges, text, &[], attrs)?;
+        } else {
+            out.write_all(encode_text(text).as_bytes())?;
+        }
+    }
+    write_pending(out, &mut pending)?;
+    close(out, &mut open, 0)?;
+    out.write_all(b"</div>")
+}
+
+/// Writes the CSS class identifying range `idx`.
+pub(crate) fn write_range_class(
+    out: &mut dyn Write,
+    config: &Spanr,
+    ranges: &Ranges,
+    idx: usize,
+) -> io::Result<()> {
+    if config.hashed_classes {
+        write!(out, "c{:016x}", ranges.range_hash(idx))
+    } else {
+        write!(out, "c{}", idx)
+    }
+}
+
+/// The attributes of a span written by [`write_html_span`], besides those
+/// given by its ranges.
+pub(crate) struct SpanAttrs {
+    /// An extra class.
+    class: Option<&'static str>,
+    /// The index of the block the span opens or closes, written as a
+    /// `data-block` attribute.
+    block: Option<usize>,
+    /// Whether the span is given the source and starting line of the last of
+    /// its ranges, as `data-source` and `data-line` attributes, for clicking
+    /// it to jump to.
+    jump: bool,
+}
+
+/// Writes a `<span>` of text covered by the ranges in `indexes`, tagged with
+/// the classes of those ranges and their sources, and titled with their
+/// labels.
+pub(crate) fn write_html_span(
+    out: &mut dyn Write,
+    config: &Spanr,
+    ranges: &Ranges,
+    text: &str,
+    indexes: &[usize],
+    attrs: SpanAttrs,
+) -> io::Result<()> {
+    write_span_start(out, config, ranges, indexes, attrs)?;
+    out.write_all(encode_text(text).as_bytes())?;
+    out.write_all(b"</span>")
+}
+
+/// Writes the opening tag of a span covered by the ranges in `indexes`, as
+/// written by [`write_html_span`].
+pub(crate) fn write_span_start(
+    out: &mut dyn Write,
+    config: &Spanr,
+    ranges: &Ranges,
+    indexes: &[usize],
+    attrs: SpanAttrs,
+) -> io::Result<()> {
+    out.write_all(b"<span class=\"")?;
+    let mut sources = Vec::new();
+    for &idx in indexes {
+        write_range_class(out, config, ranges, idx)?;
+        out.write_all(b" ")?;
+        sources.push(ranges.range_list[idx].source);
+    }
+    sources.sort_unstable();
+    sources.dedup();
+    for source in &sources {
+        write!(out, "s{} ", source)?;
+    }
+    if indexes.iter().any(|&idx| config.is_focused(ranges, idx)) {
+        out.write_all(b"focus ")?;
+    }
+    if let Some(class) = attrs.class {
+        out.write_all(class.as_bytes())?;
+    }
+    out.write_all(b"\"")?;
+    let labels: Vec<_> = indexes
+        .iter()
+        .filter_map(|&idx| config.label_of(ranges, idx))
+        .collect();
+    if !labels.is_empty() {
+        let title = labels.join("\n");
+        write!(out, " title=\"{}\"", encode_double_quoted_attribute(&title))?;
+    }
+    if let Some(block) = attrs.block {
+        write!(out, " data-block=\"{}\"", block)?;
+    }
+    if let Some(&idx) = indexes.iter().max().filter(|_| attrs.jump) {
+        let range = ranges.range_list[idx];
+        write!(
+            out,
+            " data-source=\"{}\" data-line=\"{}\"",
+            range.source, range.start.line
+        )?;
+    }
+    out.write_all(b">")
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC, to the second.
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Converts days since the epoch to a civil date, as described at
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Writes `template` to `out`, calling `write_placeholder` for each
+/// `{NAME}` placeholder found. Placeholders for which `write_placeholder`
+/// returns `false` are written out unchanged.
+pub(crate) fn write_template<W: Write>(
+    out: &mut W,
+    template: &str,
+    mut write_placeholder: impl FnMut(&mut W, &str) -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.write_all(&rest.as_bytes()[..start])?;
+        rest = &rest[start + 1..];
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_uppercase())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        if name_len > 0 && rest[name_len..].starts_with('}') && write_placeholder(out, name)? {
+            rest = &rest[name_len + 1..];
+        } else {
+            out.write_all(b"{")?;
+        }
+    }
+    out.write_all(rest.as_bytes())
+}
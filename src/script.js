@@ -0,0 +1,163 @@
+// Every element tagged with a range class, in both panels, so that
+// hovering one occurrence of a range can highlight all of them.
+const rangeClassesOf = (element) =>
+  [...element.classList].filter((className) => /^c[0-9a-f]+$/.test(className));
+const rangeElements = new Map();
+for (const element of document.querySelectorAll("#left span, #right span")) {
+  for (const className of rangeClassesOf(element)) {
+    if (!rangeElements.has(className)) {
+      rangeElements.set(className, []);
+    }
+    rangeElements.get(className).push(element);
+  }
+}
+// Spans in the source panel are nested within the spans of the ranges
+// containing them, so text there is covered by the ranges of every span
+// around it too.
+const coveringClassesOf = (element) => {
+  const res = [];
+  for (; element && element.tagName === "SPAN"; element = element.parentElement) {
+    res.push(...rangeClassesOf(element));
+  }
+  return res;
+};
+const setHover = (target, hover) => {
+  for (const className of coveringClassesOf(target)) {
+    for (const element of rangeElements.get(className)) {
+      element.classList.toggle("hover", hover);
+    }
+  }
+};
+// If enabled, the generated row under the cursor is highlighted, along
+// with the source rows of the ranges it is spanned to.
+const highlightLines = document.getElementById("left").closest(".line-highlight") !== null;
+const setLineHover = (target, hover) => {
+  const row = target.closest("#left > div");
+  if (!row) {
+    return;
+  }
+  row.classList.toggle("cursor-line", hover);
+  for (const className of coveringClassesOf(target)) {
+    for (const element of rangeElements.get(className)) {
+      const sourceRow = element.closest("#right > div");
+      if (sourceRow) {
+        sourceRow.classList.toggle("cursor-line", hover);
+      }
+    }
+  }
+};
+const onHover = (hover) => (event) => {
+  setHover(event.target, hover);
+  if (highlightLines) {
+    setLineHover(event.target, hover);
+  }
+};
+document.body.onmouseover = onHover(true);
+document.body.onmouseout = onHover(false);
+
+// The first focused chunk of each panel is scrolled into view.
+for (const panel of ["left", "right"]) {
+  const focused = document.querySelector(`#${panel} .focus`);
+  if (focused) {
+    focused.scrollIntoView({ block: "center" });
+  }
+}
+
+// Text matching the search box is highlighted in both panels, along
+// with every occurrence of any range it is spanned to.
+const search = document.getElementById("search");
+if (search) {
+  search.oninput = () => {
+    for (const element of document.querySelectorAll(".match, .match-range")) {
+      element.classList.remove("match", "match-range");
+    }
+    const query = search.value;
+    if (!query) {
+      return;
+    }
+    for (const element of document.querySelectorAll("#left span, #right span")) {
+      if (!element.textContent.includes(query)) {
+        continue;
+      }
+      element.classList.add("match");
+      for (const className of rangeClassesOf(element)) {
+        for (const linked of rangeElements.get(className)) {
+          linked.classList.add("match-range");
+        }
+      }
+    }
+  };
+}
+
+// Braces are tagged with the index of the block they delimit. Clicking
+// an opening brace folds away the rows between it and its closing
+// brace, which are hidden rather than removed.
+const blockBraces = new Map();
+for (const brace of document.querySelectorAll("#left [data-block]")) {
+  const block = brace.dataset.block;
+  if (!blockBraces.has(block)) {
+    blockBraces.set(block, []);
+  }
+  blockBraces.get(block).push(brace);
+}
+const toggleFold = (open) => {
+  const [, close] = blockBraces.get(open.dataset.block);
+  if (!close) {
+    return;
+  }
+  const folded = open.classList.toggle("folded");
+  const lastRow = close.parentElement;
+  for (
+    let row = open.parentElement.nextElementSibling;
+    row && row !== lastRow;
+    row = row.nextElementSibling
+  ) {
+    const folds = (+row.dataset.folds || 0) + (folded ? 1 : -1);
+    row.dataset.folds = folds;
+    row.classList.toggle("collapsed", folds > 0);
+  }
+};
+// Clicking any other spanned chunk scrolls the source panel to where
+// it was spanned to, falling back to the start of its source file, and
+// briefly flashes the row.
+const jumpToSource = (chunk) => {
+  const target =
+    rangeClassesOf(chunk)
+      .flatMap((className) => rangeElements.get(className))
+      .find((element) => element.closest("#right")) ||
+    document.querySelector(`#right .s${chunk.dataset.source}`);
+  const row = target && target.closest("#right > div");
+  if (!row) {
+    return;
+  }
+  row.scrollIntoView({ block: "center" });
+  row.classList.remove("flash");
+  // Restarts the animation if the row is already flashing.
+  void row.offsetWidth;
+  row.classList.add("flash");
+};
+document.getElementById("left").onclick = (event) => {
+  const { block, source } = event.target.dataset;
+  if (block !== undefined && blockBraces.get(block)[0] === event.target) {
+    toggleFold(event.target);
+  } else if (source !== undefined) {
+    jumpToSource(event.target);
+  }
+};
+
+// The theme defaults to the one the report was generated with, but the
+// reader's last choice is remembered. Storage may be unavailable for
+// local files, in which case the choice is just not remembered.
+const root = document.documentElement;
+try {
+  root.dataset.theme = localStorage.getItem("spanr-theme") || root.dataset.theme;
+} catch (e) {}
+const themeToggle = document.getElementById("theme-toggle");
+if (themeToggle) {
+  themeToggle.onclick = () => {
+    root.dataset.theme = root.dataset.theme === "dark" ? "light" : "dark";
+    try {
+      localStorage.setItem("spanr-theme", root.dataset.theme);
+    } catch (e) {}
+  };
+}
@@ -0,0 +1,508 @@
+//! Reading of the source files that ranges point into, and laying them out
+//! as the parts of the source panel.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::visitor::is_trivia;
+use crate::{Position, Ranges, Spanr};
+
+/// Provides the text of source files, for reporting on code that isn't on
+/// the file system, such as when running as WASM, or in tests.
+///
+/// Any function taking a path and returning its text is a provider.
+pub trait SourceProvider: Send + Sync {
+    /// Returns the text of the source file `path`, or `None` if it isn't
+    /// available.
+    fn read(&self, path: &Path) -> Option<String>;
+}
+
+impl<F: Fn(&Path) -> Option<String> + Send + Sync> SourceProvider for F {
+    fn read(&self, path: &Path) -> Option<String> {
+        self(path)
+    }
+}
+
+/// A [`SourceProvider`] shared by the clones of a [`Spanr`].
+#[derive(Clone)]
+pub(crate) struct SharedSourceProvider(pub(crate) Arc<dyn SourceProvider>);
+
+impl fmt::Debug for SharedSourceProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SourceProvider")
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum SeparatorType {
+    Start,
+    /// Both the start and end of a zero-width range.
+    Point,
+    End,
+}
+
+/// The start or end of a range within a source file.
+///
+/// Separators sort by position, then with all starts before all zero-width
+/// ranges before all ends, then by range index. The order of separators at
+/// the same position never changes which ranges cover a part of the source,
+/// as no text lies between them, but starting ranges before ending them means
+/// the set of covering ranges only ever grows while at a position, and a
+/// zero-width range is covered by every range around that position.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct RangeSeparator {
+    pos: Position,
+    sep: SeparatorType,
+    idx: usize,
+}
+
+struct LoadedSource {
+    path: PathBuf,
+    text: Option<Arc<[String]>>,
+    range_seps: Vec<RangeSeparator>,
+    /// The number of ranges extending past the end of the file, which are
+    /// cut short at the end of it.
+    past_end: usize,
+}
+
+/// Returns the end of `lines` if `pos` lies on a line past it.
+///
+/// Columns past the end of a line need no clamping, as they are already
+/// clamped when the line is sliced.
+pub(crate) fn clamp_to_end(lines: &[String], pos: Position) -> Option<Position> {
+    if pos.line <= lines.len().max(1) {
+        return None;
+    }
+    Some(Position {
+        line: lines.len().max(1),
+        column: lines.last().map_or(0, |line| line.chars().count()),
+    })
+}
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct SourceParts {
+    pub(crate) parts: Vec<(String, Vec<usize>)>,
+    /// The indexes of parts that lie between ranges without being covered by
+    /// any, ignoring whitespace.
+    pub(crate) unused: BTreeSet<usize>,
+    /// The indexes of the empty parts marking where zero-width ranges are.
+    pub(crate) markers: BTreeSet<usize>,
+}
+
+impl SourceParts {
+    fn add_unspanned(&mut self, s: &str) {
+        self.parts.push((s.into(), Vec::new()));
+    }
+    /// Adds an empty part marking the position of a zero-width range, covered
+    /// by it and the ranges in `indexes` around it.
+    fn add_marker(&mut self, indexes: &BTreeSet<usize>) {
+        self.markers.insert(self.parts.len());
+        self.parts
+            .push((String::new(), indexes.iter().copied().collect()));
+    }
+    /// Adds a part of source text, covered by the ranges in `indexes`.
+    ///
+    /// Comments aren't tokens, so they are never really covered by a range,
+    /// even if one spans the text around them: a part made up of only
+    /// comments and whitespace is added as unspanned.
+    fn add(&mut self, s: String, indexes: &BTreeSet<usize>) {
+        if !s.trim().is_empty() && is_trivia(&s) {
+            self.parts.push((s, Vec::new()));
+            return;
+        }
+        if indexes.is_empty() && !s.trim().is_empty() {
+            self.unused.insert(self.parts.len());
+        }
+        self.parts.push((s, indexes.iter().copied().collect()));
+    }
+}
+
+/// Returns the part of `line` between the `start` and `end` columns, or up to
+/// the end of the line if `end` is `None`.
+///
+/// Columns count `char`s rather than bytes, which is how both the compiler
+/// and proc-macro2's fallback implementation measure them.
+pub(crate) fn slice_columns(line: &str, start: usize, end: Option<usize>) -> &str {
+    let byte_offset = |column| {
+        line.char_indices()
+            .nth(column)
+            .map_or(line.len(), |(offset, _)| offset)
+    };
+    let start = byte_offset(start);
+    let end = end.map_or(line.len(), byte_offset).max(start);
+    &line[start..end]
+}
+
+/// Splits source text into lines, accepting both `\n` and `\r\n` line
+/// endings.
+///
+/// The carriage return of a `\r\n` line ending always follows the last
+/// column of its line, so removing it doesn't shift any span positions: the
+/// compiler normalizes `\r\n` to `\n` before computing them, and
+/// proc-macro2's fallback implementation counts the `\r` as a column past any
+/// token on that line.
+pub(crate) fn split_lines(text: &str) -> Vec<String> {
+    text.split_terminator('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).into())
+        .collect()
+}
+
+/// Resolves `path` to the canonical path of the file it names, or returns it
+/// unchanged if it doesn't name a file that exists, such as the path given
+/// to code parsed from a string.
+pub(crate) fn canonical_path(path: PathBuf) -> PathBuf {
+    fs::canonicalize(&path).unwrap_or(path)
+}
+
+/// Reads the lines of a source file, preferring the text given for it by
+/// [`Spanr::parsed_source`] or [`Spanr::source_override`], and then the
+/// configured [`SourceProvider`] or [`SourceCache`](crate::SourceCache),
+/// before the file system.
+pub(crate) fn read_source(path: &Path, config: &Spanr) -> io::Result<Arc<[String]>> {
+    if let Some(text) = config.source_overrides.get(path) {
+        return Ok(split_lines(text).into());
+    }
+    match (
+        &config.parsed_source,
+        &config.source_provider,
+        &config.source_cache,
+    ) {
+        (Some((parsed_path, text)), _, _) if parsed_path == path => Ok(split_lines(text).into()),
+        (_, Some(provider), _) => match provider.0.read(path) {
+            Some(text) => Ok(split_lines(&text).into()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "not available from the source provider",
+            )),
+        },
+        (_, _, Some(cache)) => cache.read(path),
+        _ => Ok(split_lines(&fs::read_to_string(path)?).into()),
+    }
+}
+
+/// Like [`slice_columns`], but with tabs expanded to spaces, up to the next
+/// multiple of the configured tab width from the start of the line.
+pub(crate) fn display_columns<'l>(
+    line: &'l str,
+    start: usize,
+    end: Option<usize>,
+    config: &Spanr,
+) -> Cow<'l, str> {
+    let tab_width = match config.tab_width {
+        Some(tab_width) if tab_width > 0 && line.contains('\t') => tab_width,
+        _ => return slice_columns(line, start, end).into(),
+    };
+    let mut res = String::new();
+    let mut display_column = 0;
+    for (column, c) in line.chars().enumerate() {
+        if end.is_some_and(|end| column >= end) {
+            break;
+        }
+        let width = if c == '\t' {
+            tab_width - display_column % tab_width
+        } else {
+            1
+        };
+        if column >= start {
+            if c == '\t' {
+                res.extend(std::iter::repeat_n(' ', width));
+            } else {
+                res.push(c);
+            }
+        }
+        display_column += width;
+    }
+    res.into()
+}
+
+/// Returns the text of `lines` between the `start` and `end` positions.
+pub(crate) fn slice_range(lines: &[String], start: Position, end: Position) -> Option<String> {
+    let line = |n: usize| lines.get(n.checked_sub(1)?).map(String::as_str);
+    if start.line == end.line {
+        return Some(slice_columns(line(start.line)?, start.column, Some(end.column)).into());
+    }
+    let mut res = String::from(slice_columns(line(start.line)?, start.column, None));
+    for n in start.line + 1..end.line {
+        res.push('\n');
+        res.push_str(line(n)?);
+    }
+    res.push('\n');
+    res.push_str(slice_columns(line(end.line)?, 0, Some(end.column)));
+    Some(res)
+}
+
+/// Returns the position reached by starting at `pos` and writing `text`.
+pub(crate) fn advance(mut pos: Position, text: &str) -> Position {
+    for c in text.chars() {
+        if c == '\n' {
+            pos.line += 1;
+            pos.column = 0;
+        } else {
+            pos.column += 1;
+        }
+    }
+    pos
+}
+
+pub(crate) fn uncovered_spans_of(
+    ranges: &Ranges,
+    config: &Spanr,
+) -> Vec<(PathBuf, Position, Position)> {
+    let mut res = Vec::new();
+    for (source, path) in ranges.source_list.iter().enumerate() {
+        let lines = match read_source(path, config) {
+            Ok(lines) => lines,
+            Err(_) => continue,
+        };
+        // Ranges are sorted by source and start position.
+        let mut covered_to = None;
+        for range in ranges
+            .range_list
+            .iter()
+            .filter(|range| range.source == source)
+        {
+            if let Some(gap_start) = covered_to.filter(|&end| end < range.start) {
+                if let Some(gap) = slice_range(&lines, gap_start, range.start) {
+                    let trimmed = gap.trim_start();
+                    let start = advance(gap_start, &gap[..gap.len() - trimmed.len()]);
+                    let trimmed = trimmed.trim_end();
+                    if !is_trivia(trimmed) {
+                        res.push((path.clone(), start, advance(start, trimmed)));
+                    }
+                }
+            }
+            covered_to = covered_to.max(Some(range.end));
+        }
+    }
+    res
+}
+
+/// How many tokens with different text must share a range for their span to
+/// be reported as suspicious.
+const SUSPICIOUS_TOKENS: usize = 3;
+
+/// Finds the ranges shared by at least [`SUSPICIOUS_TOKENS`] tokens of
+/// different text, returning each along with how many tokens it spans.
+///
+/// The tokens of one range normally all have the same text, such as an input
+/// identifier used several times, so sharing one is a sign of a span like
+/// `Span::call_site()` being used rather than that of the input. Ranges over
+/// more than one line are never suspicious, as the lines of a doc comment are
+/// all spanned to its literal.
+pub(crate) fn suspicious_spans_of(ranges: &Ranges) -> Vec<(PathBuf, Position, Position, usize)> {
+    let mut tokens: BTreeMap<usize, (usize, BTreeSet<&str>)> = BTreeMap::new();
+    for chunk in &ranges.generated {
+        if let Some(idx) = chunk.range.filter(|_| !chunk.is_layout()) {
+            let (count, texts) = tokens.entry(idx).or_default();
+            *count += 1;
+            texts.insert(&chunk.text);
+        }
+    }
+    tokens
+        .into_iter()
+        .filter(|(idx, (_, texts))| {
+            let range = ranges.range_list[*idx];
+            range.start.line == range.end.line && texts.len() >= SUSPICIOUS_TOKENS
+        })
+        .map(|(idx, (count, _))| {
+            let range = ranges.range_list[idx];
+            let path = ranges.source_list[range.source].clone();
+            (path, range.start, range.end, count)
+        })
+        .collect()
+}
+
+/// Lays out the parts of the source files referenced by `ranges`.
+///
+/// Also returns the error encountered reading each source file that couldn't
+/// be read, in which case the source is shown as unavailable.
+pub(crate) fn load_original_source(
+    ranges: &Ranges,
+    config: &Spanr,
+) -> (SourceParts, Vec<(PathBuf, io::Error)>) {
+    let mut errors = Vec::new();
+    let mut loaded_sources: Vec<_> = ranges
+        .source_list
+        .iter()
+        .map(|path| LoadedSource {
+            path: path.clone(),
+            text: read_source(path, config)
+                .map_err(|e| errors.push((path.clone(), e)))
+                .ok(),
+            range_seps: Vec::new(),
+            past_end: 0,
+        })
+        .collect();
+
+    for (idx, range) in ranges.range_list.iter().enumerate() {
+        // Lines are numbered from one, so a range on line zero doesn't point
+        // into the source. Spans without location information are reported
+        // that way, and are normally dropped before getting here.
+        if range.start.line == 0 {
+            continue;
+        }
+        if let Some(loaded_source) = loaded_sources.get_mut(range.source) {
+            let (mut start, mut end) = (range.start, range.end);
+            // A span pointing past the end of the file, as from a file that
+            // has changed since it was compiled, is cut short at the end.
+            if let Some(text) = &loaded_source.text {
+                if let Some(clamped) = clamp_to_end(text, end) {
+                    start = clamp_to_end(text, start).unwrap_or(start);
+                    end = clamped;
+                    loaded_source.past_end += 1;
+                }
+            }
+            if start == end {
+                loaded_source.range_seps.push(RangeSeparator {
+                    pos: start,
+                    sep: SeparatorType::Point,
+                    idx,
+                });
+                continue;
+            }
+            loaded_source.range_seps.push(RangeSeparator {
+                pos: start,
+                sep: SeparatorType::Start,
+                idx,
+            });
+            loaded_source.range_seps.push(RangeSeparator {
+                pos: end,
+                sep: SeparatorType::End,
+                idx,
+            });
+        }
+    }
+
+    let mut source_parts = SourceParts::default();
+    for loaded_source in &mut loaded_sources {
+        loaded_source.range_seps.sort();
+
+        source_parts.add_unspanned("\n");
+        if !config.source_header.is_empty() {
+            let path = config.display_path(&loaded_source.path);
+            for line in config.source_header.split('\n') {
+                source_parts.add_unspanned(&line.replace("{path}", &path));
+                source_parts.add_unspanned("\n");
+            }
+        }
+
+        let (text, first_sep) = match (&loaded_source.text, loaded_source.range_seps.first()) {
+            (Some(text), Some(first_sep)) => (text, first_sep),
+            _ => {
+                source_parts.add_unspanned("// <source unavailable>");
+                source_parts.add_unspanned("\n");
+                continue;
+            }
+        };
+
+        let line_text = |line: usize| text.get(line.checked_sub(1)?);
+        let mut indexes = BTreeSet::new();
+        let mut pos = first_sep.pos;
+        pos.column = 0;
+
+        // Include some unspanned context before the first range.
+        let context_lines = if config.full_source {
+            usize::MAX
+        } else {
+            config.context_lines
+        };
+        let first_line = pos.line.saturating_sub(context_lines).max(1);
+        for line_text in text
+            .iter()
+            .take(pos.line.saturating_sub(1))
+            .skip(first_line - 1)
+        {
+            source_parts.add_unspanned(&display_columns(line_text, 0, None, config));
+            source_parts.add_unspanned("\n");
+        }
+
+        for range_sep in &loaded_source.range_seps {
+            while pos.line < range_sep.pos.line {
+                if let Some(line_text) = line_text(pos.line) {
+                    let s = display_columns(line_text, pos.column, None, config);
+                    source_parts.add(s.into_owned(), &indexes);
+                }
+                source_parts.add_unspanned("\n");
+                pos.line += 1;
+                pos.column = 0;
+            }
+            if pos.column < range_sep.pos.column {
+                if let Some(line_text) = line_text(pos.line) {
+                    let s =
+                        display_columns(line_text, pos.column, Some(range_sep.pos.column), config);
+                    source_parts.add(s.into_owned(), &indexes);
+                }
+                pos.column = range_sep.pos.column;
+            }
+            match range_sep.sep {
+                SeparatorType::Start => {
+                    indexes.insert(range_sep.idx);
+                }
+                SeparatorType::Point => {
+                    let mut marker = indexes.clone();
+                    marker.insert(range_sep.idx);
+                    source_parts.add_marker(&marker);
+                }
+                SeparatorType::End => {
+                    indexes.remove(&range_sep.idx);
+                }
+            }
+        }
+        if let Some(line_text) = line_text(pos.line) {
+            let s = display_columns(line_text, pos.column, None, config);
+            source_parts.add(s.into_owned(), &indexes);
+        }
+        source_parts.add_unspanned("\n");
+
+        // Include some unspanned context after the last range.
+        for line_text in text.iter().skip(pos.line).take(context_lines) {
+            source_parts.add_unspanned(&display_columns(line_text, 0, None, config));
+            source_parts.add_unspanned("\n");
+        }
+        if loaded_source.past_end > 0 {
+            source_parts.add_unspanned(&format!(
+                "// note: {} range(s) extend past the end of this file",
+                loaded_source.past_end
+            ));
+            source_parts.add_unspanned("\n");
+        }
+    }
+    (source_parts, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{Position, Ranges};
+
+    #[test]
+    fn source_provider() {
+        let mut ranges = Ranges::default();
+        let pos = |column| Position { line: 1, column };
+        let idx = ranges.add_range("virtual.rs".into(), pos(3), pos(7));
+        let missing_idx = ranges.add_range("missing.rs".into(), pos(0), pos(1));
+        let config = crate::Spanr::new().source_provider(|path: &Path| {
+            (path == Path::new("virtual.rs")).then(|| "fn main() {}\n".to_string())
+        });
+        let (source_parts, errors) = super::load_original_source(&ranges, &config);
+        let covered = |idx| -> String {
+            source_parts
+                .parts
+                .iter()
+                .filter(|(_, indexes)| indexes.contains(&idx))
+                .map(|(text, _)| text.as_str())
+                .collect()
+        };
+        assert_eq!(covered(idx), "main");
+        assert_eq!(covered(missing_idx), "");
+        // Files the provider doesn't have aren't read from disk instead.
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, Path::new("missing.rs"));
+    }
+}
@@ -0,0 +1,177 @@
+//! A [Source Map v3] rendering of the span mapping, for consumption by
+//! existing source-map-aware tooling.
+//!
+//! The pretty-printed generated code is treated as the "generated file", and
+//! each spanned chunk is mapped back to the start of its source range.
+//!
+//! [Source Map v3]: https://sourcemaps.info/spec.html
+
+use std::fmt::Write;
+
+use proc_macro2::TokenStream;
+
+use crate::{build_span_map, SpanMap};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Appends `value` to `out` as a base64 VLQ.
+fn write_vlq(out: &mut String, value: i64) {
+    let mut vlq = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = (vlq & 0b11111) as usize;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+/// Appends `s` to `out` as a JSON string literal.
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// The values of the previous segment, which each segment is encoded
+/// relative to.
+#[derive(Default)]
+struct Previous {
+    column: i64,
+    source: i64,
+    line: i64,
+    source_column: i64,
+}
+
+impl SpanMap {
+    /// Renders the mapping from the generated code back to the source files
+    /// as a Source Map v3 JSON document.
+    ///
+    /// Unspanned chunks, such as whitespace inserted by the pretty-printer,
+    /// end the mapping of the preceding chunk. Columns are counted in `char`s
+    /// rather than UTF-16 code units, so are only exact for ASCII code.
+    pub fn to_source_map(&self) -> String {
+        let mut mappings = String::new();
+        let mut prev = Previous::default();
+        let mut column = 0;
+        // Whether the last segment on the current line was mapped to a
+        // source, and so needs ending before unspanned text.
+        let mut mapped = false;
+        let mut line_has_segment = false;
+        // Tokens spanning several lines are mapped line by line.
+        let lines = self.generated().flat_map(|(text, idx)| {
+            text.split_inclusive('\n')
+                .flat_map(move |line| match line.strip_suffix('\n') {
+                    Some("") => vec![("\n", None)],
+                    Some(line) => vec![(line, idx), ("\n", None)],
+                    None => vec![(line, idx)],
+                })
+        });
+        for (text, idx) in lines {
+            if text == "\n" {
+                mappings.push(';');
+                prev.column = 0;
+                column = 0;
+                mapped = false;
+                line_has_segment = false;
+                continue;
+            }
+            let range = idx.and_then(|idx| self.range(idx));
+            if range.is_some() || mapped {
+                if line_has_segment {
+                    mappings.push(',');
+                }
+                write_vlq(&mut mappings, column - prev.column);
+                prev.column = column;
+                if let Some(range) = range {
+                    let source = range.source as i64;
+                    let line = range.start.line as i64 - 1;
+                    let source_column = range.start.column as i64;
+                    write_vlq(&mut mappings, source - prev.source);
+                    write_vlq(&mut mappings, line - prev.line);
+                    write_vlq(&mut mappings, source_column - prev.source_column);
+                    prev.source = source;
+                    prev.line = line;
+                    prev.source_column = source_column;
+                }
+                mapped = range.is_some();
+                line_has_segment = true;
+            }
+            column += text.chars().count() as i64;
+        }
+
+        let mut out = String::from("{\"version\":3,\"sources\":[");
+        for (source, path) in self.sources() {
+            if source > 0 {
+                out.push(',');
+            }
+            write_json_string(&mut out, &path.to_string_lossy());
+        }
+        out.push_str("],\"names\":[],\"mappings\":");
+        write_json_string(&mut out, &mappings);
+        out.push('}');
+        out
+    }
+}
+
+/// Renders the mapping from the pretty-printed generated code of a token
+/// stream back to its source files as a Source Map v3 JSON document.
+pub fn to_source_map(token_stream: TokenStream) -> String {
+    build_span_map(token_stream).to_source_map()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "span-locations")]
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn vlq() {
+        let mut out = String::new();
+        for value in [0, 1, -1, 15, 16, -17, 1000] {
+            super::write_vlq(&mut out, value);
+            out.push(' ');
+        }
+        assert_eq!(out, "A C D e gB jB w+B ");
+    }
+
+    #[cfg(feature = "span-locations")]
+    #[test]
+    fn source_map() {
+        let src = "foo(a);\nlet x = \"b\";";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        let generated: String = span_map.generated().map(|(text, _)| text).collect();
+        assert_eq!(generated, "foo(a);\nlet x = \"b\";\n");
+        assert_eq!(
+            span_map.to_source_map(),
+            concat!(
+                r#"{"version":3,"sources":["foo.rs"],"names":[],"mappings":"#,
+                // Each token maps to its own column of the source, while the
+                // spaces inserted on the second line are unmapped.
+                r#""AAAA,GAAG,CAAC,CAAC,CAAC;AACN,G,CAAI,C,CAAE,C,CAAE,GAAG;""#,
+                "}"
+            )
+        );
+    }
+}
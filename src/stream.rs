@@ -0,0 +1,146 @@
+//! Rendering of HTML reports for very large token streams, writing out the
+//! generated code as it is produced rather than keeping all of it.
+
+use std::io::{self, Write};
+
+use proc_macro2::TokenStream;
+
+use crate::{
+    load_original_source, write_html_parts, write_template, Chunk, Ranges, Spanr, SpanrError,
+    TokenVisitor, REQUIRED_PLACEHOLDERS,
+};
+
+impl Spanr {
+    /// Renders the span mapping of a token stream as an HTML document,
+    /// writing each row of the generated panel to `out` as soon as it is
+    /// complete.
+    ///
+    /// Only the sources and ranges seen are kept, rather than all of the
+    /// generated code, which suits token streams of millions of tokens. The
+    /// source panel is rendered once the generated panel has been written.
+    ///
+    /// As rows are written before every range is known, ranges are numbered
+    /// in the order they're first seen, and a token stream without any spans
+    /// is rendered rather than failing with [`SpanrError::NoSpans`]. Rows
+    /// aren't reordered by [`Spanr::sort_by_source_position`], and
+    /// [`Spanr::preserve_formatting`] only restores spacing within each row.
+    /// A template with `{RIGHT}` before `{LEFT}` can't be streamed, so is
+    /// rendered as by [`Spanr::write_html`].
+    pub fn stream_html<W: Write>(
+        &self,
+        token_stream: TokenStream,
+        mut out: W,
+    ) -> Result<(), SpanrError> {
+        for &name in REQUIRED_PLACEHOLDERS {
+            if !self.template.contains(&format!("{{{}}}", name)) {
+                return Err(SpanrError::MissingPlaceholder(name));
+            }
+        }
+        self.check_palette()?;
+        let position = |name| self.template.find(name);
+        if position("{RIGHT}") < position("{LEFT}") {
+            return self.write_html(token_stream, out);
+        }
+        let mut token_stream = Some(token_stream);
+        let mut ranges = None;
+        // The colors of the ranges can only be written once they are known,
+        // so if they come first, they follow the generated panel instead.
+        let mut pending_palette = false;
+        write_template(&mut out, &self.template, |out, name| {
+            match name {
+                "LEFT" => {
+                    let res = self.stream_left(out, token_stream.take().unwrap_or_default())?;
+                    if pending_palette {
+                        self.write_palette(out, &res)?;
+                    }
+                    ranges = Some(res);
+                }
+                "RIGHT" => {
+                    let ranges = ranges.as_ref().expect("the generated panel comes first");
+                    let (source_parts, errors) = load_original_source(ranges, self);
+                    self.write_right(out, ranges, &source_parts, &errors)?;
+                }
+                "PALETTE" => match &ranges {
+                    Some(ranges) => self.write_palette(out, ranges)?,
+                    None => pending_palette = true,
+                },
+                _ => return self.write_placeholder(out, name),
+            }
+            Ok(true)
+        })?;
+        out.flush()?;
+        Ok(())
+    }
+    /// Writes the rows of the generated code panel as they are produced,
+    /// returning the sources and ranges they were spanned to.
+    fn stream_left(&self, out: &mut impl Write, token_stream: TokenStream) -> io::Result<Ranges> {
+        let mut line = 1;
+        let mut error = None;
+        let mut sink = |ranges: &Ranges, chunks: Vec<Chunk>| {
+            if error.is_some() {
+                return;
+            }
+            // Each batch of chunks ends in a line break, except for the last,
+            // and the break is written by ending the row.
+            let row = match chunks.split_last() {
+                Some((last, row)) if last.text == "\n" => row,
+                _ => &chunks,
+            };
+            let parts = row.iter().map(|chunk| {
+                let class = self.highlight_class(chunk);
+                (chunk.text.as_str(), chunk.range, class, chunk.block)
+            });
+            if let Err(e) = write_html_parts(out, self, ranges, parts, true, &mut line) {
+                error = Some(e);
+            }
+        };
+        let mut visitor = TokenVisitor::new(self);
+        visitor.row_sink = Some(&mut sink);
+        visitor.visit_root(token_stream);
+        visitor.flush_rows();
+        let ranges = std::mem::take(&mut visitor.ranges);
+        drop(visitor);
+        match error {
+            Some(e) => Err(e),
+            None => Ok(ranges),
+        }
+    }
+}
+
+/// Renders the span mapping of a token stream as an HTML document, writing
+/// each row of generated code to `out` as soon as it is complete, for token
+/// streams too large to keep in memory.
+pub fn stream_html<W: Write>(token_stream: TokenStream, out: W) -> Result<(), SpanrError> {
+    Spanr::default().stream_html(token_stream, out)
+}
+
+#[cfg(all(test, feature = "span-locations"))]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn streamed() {
+        let src = "fn foo() {\n    bar(\"baz\");\n}\n";
+        let config = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .line_numbers(true);
+        let mut streamed = Vec::new();
+        config
+            .stream_html(src.parse::<TokenStream>().unwrap(), &mut streamed)
+            .unwrap();
+        let streamed = String::from_utf8(streamed).unwrap();
+        let html = config.build_html(src.parse().unwrap()).unwrap();
+        // Ranges are seen in source order, so nothing differs but where the
+        // colors are written.
+        let palette_start = html.find("<style>.c0").unwrap();
+        let palette_end = palette_start + html[palette_start..].find("</style>").unwrap() + 8;
+        let palette = &html[palette_start..palette_end];
+        let without_palette = html.replace(palette, "");
+        let left_end = without_palette
+            .find("</div>\n    <div id=\"right\">")
+            .unwrap();
+        let mut expected = without_palette;
+        expected.insert_str(left_end, palette);
+        assert_eq!(streamed, expected);
+    }
+}
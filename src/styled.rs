@@ -0,0 +1,120 @@
+//! A representation of the span mapping as lines of styled text, for
+//! rendering in terminal user interfaces.
+
+use proc_macro2::TokenStream;
+
+use crate::{SpanMap, Spanr};
+
+/// A run of text on one line, along with the range it should be colored by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StyledSpan {
+    /// The text of the span, which never contains a line break.
+    pub text: String,
+    /// The index of the range the text is colored by, or `None` if it isn't
+    /// spanned. Indexes can be mapped to colors by cycling through a palette.
+    pub color_idx: Option<usize>,
+}
+
+/// Splits chunks of text into lines of styled spans.
+///
+/// Consecutive chunks with the same color on a line share a span, and a final
+/// line break doesn't start another line.
+fn styled_lines<'a>(
+    chunks: impl IntoIterator<Item = (&'a str, Option<usize>)>,
+) -> Vec<Vec<StyledSpan>> {
+    let mut res: Vec<Vec<StyledSpan>> = vec![Vec::new()];
+    for (text, color_idx) in chunks {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                res.push(Vec::new());
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let spans = res.last_mut().unwrap();
+            match spans.last_mut() {
+                Some(span) if span.color_idx == color_idx => span.text.push_str(line),
+                _ => spans.push(StyledSpan {
+                    text: line.into(),
+                    color_idx,
+                }),
+            }
+        }
+    }
+    if res.len() > 1 && res.last().is_some_and(Vec::is_empty) {
+        res.pop();
+    }
+    res
+}
+
+impl SpanMap {
+    /// Splits the pretty-printed generated code into lines of spans, each
+    /// colored by the range it was spanned to.
+    pub fn to_styled_lines(&self) -> Vec<Vec<StyledSpan>> {
+        styled_lines(self.generated())
+    }
+    /// Splits the reconstructed source code into lines of spans, colored the
+    /// same way as the generated code.
+    ///
+    /// Text covered by several ranges is colored by the one with the highest
+    /// index, as in [`SpanMap::to_ansi`].
+    pub fn to_styled_source_lines(&self) -> Vec<Vec<StyledSpan>> {
+        styled_lines(
+            self.source_parts()
+                .map(|(text, indexes)| (text, indexes.iter().max().copied())),
+        )
+    }
+}
+
+impl Spanr {
+    /// Splits the pretty-printed generated code of a token stream into lines
+    /// of spans, each colored by the range it was spanned to.
+    pub fn to_styled_lines(&self, token_stream: TokenStream) -> Vec<Vec<StyledSpan>> {
+        self.build_span_map(token_stream).to_styled_lines()
+    }
+}
+
+/// Splits the pretty-printed generated code of a token stream into lines of
+/// spans, each colored by the range it was spanned to.
+pub fn to_styled_lines(token_stream: TokenStream) -> Vec<Vec<StyledSpan>> {
+    Spanr::default().to_styled_lines(token_stream)
+}
+
+#[cfg(all(test, feature = "span-locations"))]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    use super::StyledSpan;
+
+    #[test]
+    fn styled_lines() {
+        let src = "fn foo() {\n    bar\n}";
+        let x: TokenStream = src.parse().unwrap();
+        let span_map = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_span_map(x);
+        let lines = span_map.to_styled_lines();
+        let text: Vec<String> = lines
+            .iter()
+            .map(|line| line.iter().map(|span| span.text.as_str()).collect())
+            .collect();
+        assert_eq!(text, ["fn foo() {", "    bar", "}"]);
+        let bar = lines[1].iter().find(|span| span.text == "bar").unwrap();
+        let (_, start, _) = span_map.resolve(bar.color_idx.unwrap()).unwrap();
+        assert_eq!((start.line, start.column), (2, 4));
+        assert_eq!(
+            lines[1][0],
+            StyledSpan {
+                text: "    ".into(),
+                color_idx: None,
+            }
+        );
+
+        // The same ranges color the source.
+        let source_lines = span_map.to_styled_source_lines();
+        assert!(source_lines
+            .iter()
+            .flatten()
+            .any(|span| span.text == "bar" && span.color_idx == bar.color_idx));
+    }
+}
@@ -0,0 +1,161 @@
+//! A static SVG rendering of the two-panel view, for embedding where HTML
+//! can't be used.
+
+use std::fmt::Write;
+
+use html_escape::encode_text;
+use proc_macro2::TokenStream;
+
+use crate::{SpanMap, Spanr, SpanrError};
+
+/// The font size of the rendered code, in pixels.
+const FONT_SIZE: f64 = 14.0;
+/// The horizontal advance of each character of the monospaced font.
+const CHAR_WIDTH: f64 = FONT_SIZE * 0.6;
+/// The height of each line.
+const LINE_HEIGHT: f64 = FONT_SIZE * 1.3;
+/// The padding around and between the two panels.
+const PADDING: f64 = 10.0;
+
+/// A line of a panel, as chunks of text along with the ranges covering them.
+type Row<'a> = Vec<(&'a str, Vec<usize>)>;
+
+fn split_rows<'a, T: IntoIterator<Item = usize>>(
+    chunks: impl IntoIterator<Item = (&'a str, T)>,
+) -> Vec<Row<'a>> {
+    let mut rows = vec![Vec::new()];
+    for (text, indexes) in chunks {
+        let indexes: Vec<usize> = indexes.into_iter().collect();
+        // Tokens spanning several lines are split across rows.
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                rows.push(Vec::new());
+            }
+            if !line.is_empty() {
+                rows.last_mut().unwrap().push((line, indexes.clone()));
+            }
+        }
+    }
+    rows
+}
+
+fn row_width(row: &Row) -> usize {
+    row.iter().map(|(text, _)| text.chars().count()).sum()
+}
+
+impl SpanMap {
+    /// Renders both panels as an SVG image, with the generated code on the
+    /// left and the source code on the right.
+    ///
+    /// Spanned text is highlighted with the same colors as in the HTML
+    /// report. Long lines are not wrapped.
+    pub fn to_svg(&self) -> String {
+        let colors: Vec<_> = self
+            .ranges
+            .range_colors()
+            .into_iter()
+            .map(|(hue, step)| format!("hsl({}, 90%, {}%)", hue, 84 - step * 5))
+            .collect();
+        let left = split_rows(self.generated());
+        let right = split_rows(
+            self.source_parts()
+                .map(|(text, indexes)| (text, indexes.iter().copied())),
+        );
+        let left_width = left.iter().map(row_width).max().unwrap_or(0) as f64 * CHAR_WIDTH;
+        let right_width = right.iter().map(row_width).max().unwrap_or(0) as f64 * CHAR_WIDTH;
+        let width = left_width + right_width + PADDING * 3.0;
+        let height = left.len().max(right.len()) as f64 * LINE_HEIGHT + PADDING * 2.0;
+
+        let mut res = String::new();
+        let _ = writeln!(
+            res,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"Consolas, monospace\" font-size=\"{}\">",
+            width, height, FONT_SIZE
+        );
+        let _ = writeln!(res, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+        for (panel, x) in [(&left, PADDING), (&right, left_width + PADDING * 2.0)] {
+            for (line, row) in panel.iter().enumerate() {
+                let y = PADDING + line as f64 * LINE_HEIGHT;
+                let mut column = 0;
+                for (text, indexes) in row {
+                    let chunk_x = x + column as f64 * CHAR_WIDTH;
+                    let chunk_width = text.chars().count();
+                    column += chunk_width;
+                    // Where ranges overlap, the color of the last range wins,
+                    // as its rule comes last in the HTML report.
+                    let color = indexes.iter().max().map(|&idx| &colors[idx]);
+                    if let Some(color) = color {
+                        let _ = writeln!(
+                            res,
+                            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                            chunk_x,
+                            y,
+                            chunk_width as f64 * CHAR_WIDTH,
+                            LINE_HEIGHT,
+                            color
+                        );
+                    }
+                    if !text.trim().is_empty() {
+                        let _ = writeln!(
+                            res,
+                            "<text x=\"{}\" y=\"{}\" xml:space=\"preserve\">{}</text>",
+                            chunk_x,
+                            y + FONT_SIZE,
+                            encode_text(text)
+                        );
+                    }
+                }
+            }
+        }
+        res.push_str("</svg>\n");
+        res
+    }
+}
+
+impl Spanr {
+    /// Renders the span mapping of a token stream as an SVG image.
+    ///
+    /// Returns [`SpanrError::NoSpans`] if none of the tokens could be mapped
+    /// back to a real source file.
+    pub fn build_svg(&self, token_stream: TokenStream) -> Result<String, SpanrError> {
+        let span_map = self.build_span_map(token_stream);
+        if span_map.ranges.range_list.is_empty() {
+            return Err(SpanrError::NoSpans);
+        }
+        Ok(span_map.to_svg())
+    }
+}
+
+/// Renders the span mapping of a token stream as an SVG image.
+pub fn generate_svg(token_stream: TokenStream) -> Result<String, SpanrError> {
+    Spanr::default().build_svg(token_stream)
+}
+
+#[cfg(all(test, feature = "span-locations"))]
+mod tests {
+    use proc_macro2::TokenStream;
+
+    #[test]
+    fn svg_layout() {
+        let src = "fn foo() {}";
+        let x: TokenStream = src.parse().unwrap();
+        let svg = crate::Spanr::new()
+            .parsed_source("foo.rs", src)
+            .build_svg(x)
+            .unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">fn</text>"));
+        assert!(svg.contains(">// foo.rs</text>"));
+        // `fn` is colored identically in both panels.
+        let fn_rects: Vec<_> = svg
+            .lines()
+            .zip(svg.lines().skip(1))
+            .filter(|(_, next)| next.ends_with(">fn</text>"))
+            .map(|(rect, _)| &rect[rect.find("fill").unwrap()..])
+            .collect();
+        assert_eq!(fn_rects.len(), 2);
+        assert_eq!(fn_rects[0], fn_rects[1]);
+    }
+}
@@ -0,0 +1,1085 @@
+//! Walking of token streams, pretty-printing the generated code and recording
+//! the ranges of source code its tokens were spanned to.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use proc_macro2::{Delimiter, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use crate::source::{read_source, slice_range};
+use crate::{Chunk, Highlight, Position, Range, Ranges, Spanr, TokenKind, SECTION_RULE};
+
+/// The kind of the last token written, which determines whether a space is
+/// inserted before the next one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Prev {
+    /// Nothing has been written on the current line, or the last token was
+    /// an opening delimiter.
+    Open,
+    /// An identifier, literal or closing delimiter.
+    Word,
+    /// A keyword, after which operators are treated as unary.
+    Keyword,
+    /// Punctuation that should be followed by a space.
+    SpacedPunct,
+    /// Punctuation that binds to the following token.
+    TightPunct,
+}
+
+/// Multi-character operators, longest first.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "^=", "&=", "|=", "<<", ">>", "..",
+];
+
+/// Keywords which may be followed by a unary operator or a parenthesized
+/// expression, rather than acting like an identifier.
+const EXPR_KEYWORDS: &[&str] = &[
+    "as", "box", "break", "dyn", "else", "for", "if", "impl", "in", "let", "match", "move", "mut",
+    "return", "unsafe", "where", "while", "yield",
+];
+
+/// Keywords which introduce a named item that may have generic parameters.
+const ITEM_KEYWORDS: &[&str] = &["enum", "fn", "struct", "trait", "type", "union"];
+
+/// Strict and reserved keywords, which are highlighted as such.
+pub(crate) const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "union", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Returns whether `ident` is capitalized like a type, rather than being all
+/// uppercase like a constant.
+fn looks_like_type_name(ident: &str) -> bool {
+    let ident = ident.strip_prefix("r#").unwrap_or(ident);
+    let mut chars = ident.chars();
+    chars.next().is_some_and(char::is_uppercase)
+        && (chars.clone().next().is_none() || chars.any(char::is_lowercase))
+}
+
+pub(crate) struct TokenVisitor<'a> {
+    config: &'a Spanr,
+    pub(crate) ranges: Ranges,
+    indent: usize,
+    newline: bool,
+    prev: Prev,
+    /// Jointly-spaced punctuation that forms an operator with the punctuation
+    /// that follows it.
+    puncts: Vec<Punct>,
+    /// The number of generic argument lists currently open.
+    angle_depth: usize,
+    /// Whether the last token written was a `::` path separator.
+    after_path_sep: bool,
+    /// The last token written, if it was an identifier.
+    last_ident: String,
+    /// Whether the last token written was an identifier following a keyword
+    /// that introduces a named item, such as `fn`.
+    after_item_keyword: bool,
+    /// Whether the last token written was the `'` of a lifetime.
+    after_lifetime_quote: bool,
+    /// Whether a `|` opening the parameters of a closure has been written,
+    /// but not the `|` closing them.
+    ///
+    /// This is a guess, and an or-pattern with a leading `|`, as in
+    /// `match x { | A | B => .. }`, is mistaken for closure parameters.
+    in_closure_params: bool,
+    /// The number of characters written on the current line.
+    column: usize,
+    /// Whether the current line continues one that was too wide.
+    continuation: bool,
+    /// The source of the last spanned token written.
+    last_source: Option<usize>,
+    /// The indexes of the brace-delimited blocks currently open.
+    pub(crate) blocks: Vec<usize>,
+    /// The number of brace-delimited blocks opened so far.
+    pub(crate) block_count: usize,
+    /// Whether the bounds of a `where` clause are being written, each on a
+    /// line of its own.
+    in_where: bool,
+    /// The lines of each source file read so far, or `None` if it couldn't
+    /// be read.
+    source_lines: HashMap<PathBuf, Option<Arc<[String]>>>,
+    /// Whether each path seen is within the source root.
+    in_root: HashMap<PathBuf, bool>,
+    /// Where rows of generated code are passed as they are completed, if
+    /// they are being streamed rather than kept.
+    pub(crate) row_sink: Option<&'a mut RowSink<'a>>,
+}
+
+/// A callback passed each batch of chunks of generated code written, along
+/// with the ranges seen so far.
+pub(crate) type RowSink<'a> = dyn FnMut(&Ranges, Vec<Chunk>) + 'a;
+
+/// Returns the source file and extent of a span, if it points into a real
+/// source file.
+///
+/// Spans of token streams parsed from a string at runtime are attributed to
+/// `parsed_source`, if given.
+#[cfg(feature = "span-locations")]
+fn span_location(
+    span: Span,
+    parsed_source: Option<&Path>,
+) -> Option<(PathBuf, Position, Position)> {
+    let path = match span.local_file() {
+        Some(path) => path,
+        None if span.file().starts_with("<parsed string") => parsed_source?.into(),
+        None => return None,
+    };
+    let start: Position = span.start().into();
+    let end: Position = span.end().into();
+    // Without location information, the compiler reports every span as
+    // starting on line zero.
+    if start.line == 0 {
+        return None;
+    }
+    Some((path, start, end))
+}
+
+#[cfg(not(feature = "span-locations"))]
+fn span_location(
+    _span: Span,
+    _parsed_source: Option<&Path>,
+) -> Option<(PathBuf, Position, Position)> {
+    None
+}
+
+impl TokenVisitor<'_> {
+    pub(crate) fn add_span(&mut self, span: Span, kind: TokenKind) -> Option<usize> {
+        let (path, start, end) = self.locate_span(span, kind)?;
+        Some(self.ranges.add_range(path, start, end))
+    }
+    /// Returns the source file and extent of the span of a token of the
+    /// given kind, unless the configuration leaves it out of the mapping.
+    fn locate_span(
+        &mut self,
+        span: Span,
+        kind: TokenKind,
+    ) -> Option<(PathBuf, Position, Position)> {
+        if let Some(only_token_kinds) = &self.config.only_token_kinds {
+            if !only_token_kinds.contains(&kind) {
+                return None;
+            }
+        }
+        let parsed_source = self.config.parsed_source.as_ref();
+        let (path, start, end) =
+            span_location(span, parsed_source.map(|(path, _)| path.as_path()))?;
+        if let Some(only_sources) = &self.config.only_sources {
+            if !only_sources.contains(&path) {
+                return None;
+            }
+        }
+        if !self.in_source_root(&path) {
+            return None;
+        }
+        Some((path, start, end))
+    }
+    /// Returns whether `path` is within the source root, if one is set,
+    /// remembering the files found outside of it.
+    ///
+    /// Paths that don't name a file that exists are never outside the root,
+    /// as nothing of them can be read.
+    fn in_source_root(&mut self, path: &Path) -> bool {
+        let root = match &self.config.source_root {
+            Some(root) => root,
+            None => return true,
+        };
+        if let Some(&in_root) = self.in_root.get(path) {
+            return in_root;
+        }
+        let in_root = match fs::canonicalize(path) {
+            Ok(canonical) if !canonical.starts_with(root) => {
+                self.ranges.outside_root.insert(canonical);
+                false
+            }
+            _ => true,
+        };
+        self.in_root.insert(path.into(), in_root);
+        in_root
+    }
+    /// Returns the source text a span points at, if it can be read.
+    fn original_text(&mut self, span: Span) -> Option<String> {
+        let parsed_source = self.config.parsed_source.as_ref();
+        let (path, start, end) =
+            span_location(span, parsed_source.map(|(path, _)| path.as_path()))?;
+        self.source_text(path, start, end)
+            .filter(|text| !text.is_empty())
+    }
+    /// Returns the text of a source file between two positions, if it can be
+    /// read.
+    fn source_text(&mut self, path: PathBuf, start: Position, end: Position) -> Option<String> {
+        let config = self.config;
+        let lines = self
+            .source_lines
+            .entry(path)
+            .or_insert_with_key(|path| read_source(path, config).ok())
+            .as_ref()?;
+        slice_range(lines, start, end)
+    }
+    /// Replaces the spacing written between consecutive spanned chunks with
+    /// the source text between their ranges, where it is only whitespace and
+    /// comments.
+    ///
+    /// Chunks are only respaced when their ranges follow one another in the
+    /// same source file, and nothing but whitespace was written between them.
+    fn restore_source_spacing(&mut self) {
+        let chunks = std::mem::take(&mut self.ranges.generated);
+        let mut res = Vec::with_capacity(chunks.len());
+        // The position in `res` of the last spanned chunk, along with its
+        // range, if only whitespace has been written since.
+        let mut last: Option<(usize, Range)> = None;
+        for chunk in chunks {
+            let range = match chunk.range {
+                Some(idx) => self.ranges.range_list[idx],
+                None => {
+                    if !chunk.text.trim().is_empty() {
+                        last = None;
+                    }
+                    res.push(chunk);
+                    continue;
+                }
+            };
+            if let Some((at, prev)) = last {
+                let gap = if prev.source == range.source && prev.end <= range.start {
+                    let path = self.ranges.source_list[range.source].clone();
+                    self.source_text(path, prev.end, range.start)
+                        .filter(|gap| is_trivia(gap))
+                } else {
+                    None
+                };
+                if let Some(gap) = gap {
+                    res.truncate(at + 1);
+                    let unspanned = |text: &str| Chunk {
+                        text: text.into(),
+                        range: None,
+                        highlight: None,
+                        block: None,
+                        layout: true,
+                    };
+                    for (i, line) in gap.split('\n').enumerate() {
+                        if i > 0 {
+                            res.push(unspanned("\n"));
+                        }
+                        if !line.is_empty() {
+                            res.push(unspanned(line));
+                        }
+                    }
+                }
+            }
+            res.push(chunk);
+            last = Some((res.len() - 1, range));
+        }
+        self.ranges.generated = res;
+    }
+    /// Writes text laying out the generated code, rather than a token.
+    pub(crate) fn add_str(&mut self, s: &str) {
+        self.push_chunk(s, None, None, true);
+    }
+    pub(crate) fn add_token(
+        &mut self,
+        s: &str,
+        range_idx: Option<usize>,
+        highlight: Option<Highlight>,
+    ) {
+        self.push_chunk(s, range_idx, highlight, false);
+    }
+    fn push_chunk(
+        &mut self,
+        s: &str,
+        range_idx: Option<usize>,
+        highlight: Option<Highlight>,
+        layout: bool,
+    ) {
+        // A token may itself span several lines, such as a multi-line
+        // string, leaving the line it ends on partly written.
+        match s.rsplit_once('\n') {
+            Some((_, last)) => {
+                self.column = last.chars().count();
+                self.continuation = false;
+            }
+            None => self.column += s.chars().count(),
+        }
+        self.ranges.generated.push(Chunk {
+            text: s.into(),
+            range: range_idx,
+            highlight,
+            block: None,
+            layout,
+        });
+        if s == "\n" && layout {
+            self.flush_rows();
+        }
+    }
+    /// Passes the chunks written since the last flush to the row sink, if
+    /// rows are being streamed, rather than keeping them.
+    ///
+    /// Source formatting is restored first, so it is only restored within
+    /// each batch of rows.
+    pub(crate) fn flush_rows(&mut self) {
+        if self.row_sink.is_none() {
+            return;
+        }
+        if self.config.preserve_formatting {
+            self.restore_source_spacing();
+        }
+        let chunks = std::mem::take(&mut self.ranges.generated);
+        if let Some(sink) = &mut self.row_sink {
+            sink(&self.ranges, chunks);
+        }
+    }
+    /// Writes a line labeling the source of the range `range_idx`, if it
+    /// differs from the source of the last spanned token.
+    fn label_source(&mut self, range_idx: Option<usize>) {
+        let source = match range_idx {
+            Some(range_idx) => self.ranges.range_list[range_idx].source,
+            None => return,
+        };
+        if self.last_source.replace(source) == Some(source) {
+            return;
+        }
+        if !self.newline {
+            if self
+                .ranges
+                .generated
+                .last()
+                .is_some_and(|chunk| chunk.text == " ")
+            {
+                self.ranges.generated.pop();
+            }
+            self.newline = true;
+            self.add_str("\n");
+        }
+        self.start_line();
+        let label = format!(
+            "// --- {} ---",
+            self.config.display_path(&self.ranges.source_list[source])
+        );
+        self.add_str(&label);
+        self.add_str("\n");
+        self.newline = true;
+    }
+    /// Emits indentation if nothing has been written on the current line yet.
+    fn start_line(&mut self) {
+        if self.newline {
+            self.newline = false;
+            let mut indent = self.indent + usize::from(self.continuation);
+            if let Some(max_indent) = self.config.max_indent {
+                indent = indent.min(max_indent);
+            }
+            for _ in 0..indent {
+                self.add_str(&self.config.indent_unit);
+            }
+        }
+    }
+    /// Writes out a token, indenting it if it starts a line.
+    ///
+    /// Tokens spanning several lines are written verbatim, as the lines after
+    /// the first are part of the token, so aren't indented.
+    fn visit_str(&mut self, s: &str, span: Span, kind: TokenKind, highlight: Option<Highlight>) {
+        if s.is_empty() {
+            return;
+        }
+        let range_idx = self.add_span(span, kind);
+        if s == "}" {
+            self.indent = self.indent.saturating_sub(1);
+            if !self.newline {
+                self.newline = true;
+                self.add_str("\n");
+            }
+        }
+        if self.config.group_by_source {
+            self.label_source(range_idx);
+        }
+        self.start_line();
+        self.add_token(s, range_idx, highlight);
+        let block = match s {
+            "{" => {
+                self.blocks.push(self.block_count);
+                self.block_count += 1;
+                self.blocks.last().copied()
+            }
+            "}" => self.blocks.pop(),
+            _ => None,
+        };
+        if let Some(chunk) = self.ranges.generated.last_mut() {
+            chunk.block = block;
+        }
+        match s {
+            "{" => {
+                self.indent += 1;
+                self.newline = true;
+                self.add_str("\n");
+            }
+            ";" | "}" => {
+                self.newline = true;
+                self.add_str("\n");
+            }
+            _ => {}
+        }
+        if self.newline {
+            self.prev = Prev::Open;
+        }
+    }
+    /// Writes out a literal, split into its delimiters and contents if
+    /// configured to.
+    ///
+    /// Each part is spanned to its own part of the literal's range, which is
+    /// only known where the literal is on one line and as wide as its range.
+    /// Otherwise the literal is spanned as a whole.
+    fn visit_literal(&mut self, s: &str, span: Span) {
+        let highlight = Some(Highlight::of_literal(s));
+        let parts = match self.config.split_literals {
+            true => literal_delimiters(s),
+            false => None,
+        };
+        // Only the parts are mapped, so the whole literal isn't given a range.
+        let location = parts.and_then(|_| self.locate_span(span, TokenKind::Literal));
+        let (open, close, path, start) = match (parts, location) {
+            (Some((open, close)), Some((path, start, end)))
+                if start.line == end.line
+                    && end.column.checked_sub(start.column) == Some(s.chars().count()) =>
+            {
+                (open, close, path, start)
+            }
+            _ => return self.visit_str(s, span, TokenKind::Literal, highlight),
+        };
+        let column = |offset: usize| Position {
+            line: start.line,
+            column: start.column + s[..offset].chars().count(),
+        };
+        let mut parts = Vec::new();
+        for (start, end) in [(0, open), (open, close), (close, s.len())] {
+            if start != end {
+                let part_idx = self
+                    .ranges
+                    .add_range(path.clone(), column(start), column(end));
+                parts.push((&s[start..end], part_idx));
+            }
+        }
+        if self.config.group_by_source {
+            self.label_source(parts.first().map(|&(_, part_idx)| part_idx));
+        }
+        self.start_line();
+        for (part, part_idx) in parts {
+            self.add_token(part, Some(part_idx), highlight);
+        }
+    }
+    /// Ends the current line, unless nothing has been written on it.
+    fn end_line(&mut self) {
+        if !self.newline {
+            self.newline = true;
+            self.add_str("\n");
+            self.prev = Prev::Open;
+        }
+    }
+    /// Ends a `where` clause being written with a bound on each line.
+    fn end_where(&mut self) {
+        if self.in_where {
+            self.in_where = false;
+            self.indent = self.indent.saturating_sub(1);
+        }
+    }
+    /// Records the kind of the last token written, unless it ended the line.
+    fn set_prev(&mut self, prev: Prev) {
+        if !self.newline {
+            self.prev = prev;
+        }
+    }
+    /// Writes a space before the next token, of `next_len` characters, if
+    /// both it and the previous token allow one.
+    ///
+    /// If the next token would take the line past the configured maximum
+    /// width and follows a comma, a line break is written instead, and the
+    /// line is continued one level further indented.
+    fn space_before(&mut self, allowed: bool, next_len: usize) {
+        let after_comma = self
+            .ranges
+            .generated
+            .last()
+            .is_some_and(|chunk| chunk.text == ",");
+        let too_wide = self
+            .config
+            .max_width
+            .is_some_and(|max_width| self.column + 1 + next_len > max_width);
+        if !self.newline && after_comma && too_wide {
+            self.add_str("\n");
+            self.newline = true;
+            self.continuation = true;
+            self.prev = Prev::Open;
+            return;
+        }
+        if allowed && matches!(self.prev, Prev::Word | Prev::Keyword | Prev::SpacedPunct) {
+            self.add_str(" ");
+        }
+    }
+    fn visit_token_stream(&mut self, token_stream: TokenStream) {
+        let token_trees: Vec<_> = token_stream.into_iter().collect();
+        let mut rest = &token_trees[..];
+        while let Some((token_tree, tail)) = rest.split_first() {
+            rest = match self.visit_doc_comment(rest) {
+                Some(tail) => tail,
+                None => match self.visit_attribute(rest) {
+                    Some(tail) => tail,
+                    None => {
+                        self.visit_token_tree(token_tree.clone());
+                        tail
+                    }
+                },
+            };
+        }
+    }
+    /// Writes out a `#[...]` or `#![...]` attribute at the start of
+    /// `token_trees`, returning the remaining token trees.
+    ///
+    /// Attributes written at the start of a line are given a line of their
+    /// own, as they would be in handwritten code.
+    fn visit_attribute<'t>(&mut self, token_trees: &'t [TokenTree]) -> Option<&'t [TokenTree]> {
+        let len = match token_trees {
+            [TokenTree::Punct(hash), TokenTree::Punct(bang), TokenTree::Group(group), ..]
+                if hash.as_char() == '#'
+                    && bang.as_char() == '!'
+                    && group.delimiter() == Delimiter::Bracket =>
+            {
+                3
+            }
+            [TokenTree::Punct(hash), TokenTree::Group(group), ..]
+                if hash.as_char() == '#' && group.delimiter() == Delimiter::Bracket =>
+            {
+                2
+            }
+            _ => return None,
+        };
+        self.flush_puncts();
+        let own_line = self.newline;
+        for token_tree in &token_trees[..len] {
+            self.visit_token_tree(token_tree.clone());
+        }
+        self.flush_puncts();
+        if own_line && !self.newline {
+            self.newline = true;
+            self.add_str("\n");
+            self.prev = Prev::Open;
+        }
+        Some(&token_trees[len..])
+    }
+    /// Renders a `#[doc = "..."]` or `#![doc = "..."]` attribute at the start
+    /// of `token_trees` as the doc comment it was desugared from, returning
+    /// the remaining token trees.
+    fn visit_doc_comment<'t>(&mut self, token_trees: &'t [TokenTree]) -> Option<&'t [TokenTree]> {
+        let (prefix, group, rest) = match token_trees {
+            [TokenTree::Punct(hash), TokenTree::Punct(bang), TokenTree::Group(group), rest @ ..]
+                if hash.as_char() == '#' && bang.as_char() == '!' =>
+            {
+                ("//!", group, rest)
+            }
+            [TokenTree::Punct(hash), TokenTree::Group(group), rest @ ..]
+                if hash.as_char() == '#' =>
+            {
+                ("///", group, rest)
+            }
+            _ => return None,
+        };
+        if group.delimiter() != Delimiter::Bracket {
+            return None;
+        }
+        let attr: Vec<_> = group.stream().into_iter().collect();
+        let literal = match &attr[..] {
+            [TokenTree::Ident(ident), TokenTree::Punct(eq), TokenTree::Literal(literal)]
+                if ident == "doc" && eq.as_char() == '=' =>
+            {
+                literal
+            }
+            _ => return None,
+        };
+        let text = parse_str_literal(&literal.to_string())?;
+
+        self.flush_puncts();
+        let range_idx = self.add_span(literal.span(), TokenKind::Literal);
+        if !self.newline {
+            self.newline = true;
+            self.add_str("\n");
+        }
+        for line in text.split('\n') {
+            self.start_line();
+            self.add_token(&format!("{}{}", prefix, line), range_idx, None);
+            self.newline = true;
+            self.add_str("\n");
+        }
+        self.prev = Prev::Open;
+        Some(rest)
+    }
+    /// Writes out the pending chain of jointly-spaced punctuation, spaced
+    /// according to the operators it forms.
+    fn flush_puncts(&mut self) {
+        let puncts = std::mem::take(&mut self.puncts);
+        let chain: String = puncts.iter().map(Punct::as_char).collect();
+        let mut offset = 0;
+        while offset < chain.len() {
+            let rest = &chain[offset..];
+            // A `<` joined to a `=` is always a comparison.
+            let opens_generics =
+                rest.starts_with('<') && !rest.starts_with("<=") && self.opens_generics();
+            let len = if opens_generics || (self.angle_depth > 0 && rest.starts_with('>')) {
+                // Within generics, `>>` closes two argument lists, and `<<`
+                // opens two.
+                1
+            } else {
+                OPERATORS
+                    .iter()
+                    .find(|op| rest.starts_with(*op))
+                    .map_or(1, |op| op.len())
+            };
+            let op = &rest[..len];
+            let is_word = self.prev == Prev::Word;
+            let (space, prev) = match op {
+                "." | "::" | ".." | "..=" | "..." => (!is_word, Prev::TightPunct),
+                "," | ";" | ":" => (false, Prev::SpacedPunct),
+                "?" if is_word => (false, Prev::Word),
+                "!" if is_word => (false, Prev::TightPunct),
+                // A `$` starts a metavariable, or `$crate`, in a macro body.
+                "?" | "!" | "'" | "#" | "$" => (true, Prev::TightPunct),
+                "&" | "&&" | "*" | "-" if !is_word => (true, Prev::TightPunct),
+                // A `|` that can't be a binary operator opens the parameters
+                // of a closure, and the next `|` closes them.
+                "|" if self.in_closure_params => {
+                    self.in_closure_params = false;
+                    (false, Prev::SpacedPunct)
+                }
+                "|" if !is_word => {
+                    self.in_closure_params = true;
+                    (true, Prev::TightPunct)
+                }
+                "<" if opens_generics => {
+                    self.angle_depth += 1;
+                    (false, Prev::TightPunct)
+                }
+                ">" if self.angle_depth > 0 => {
+                    self.angle_depth -= 1;
+                    (false, Prev::Word)
+                }
+                _ => (true, Prev::SpacedPunct),
+            };
+            // Jointly-spaced punctuation is never separated.
+            self.space_before(space && offset == 0, len);
+            let highlight = (op == "'").then_some(Highlight::Lifetime);
+            for punct in &puncts[offset..offset + len] {
+                let punct_str = punct.as_char().to_string();
+                self.visit_str(&punct_str, punct.span(), TokenKind::Punct, highlight);
+            }
+            self.after_lifetime_quote = highlight.is_some();
+            self.set_prev(prev);
+            if self.in_where && self.angle_depth == 0 {
+                match op {
+                    "," => self.end_line(),
+                    ";" => self.end_where(),
+                    _ => {}
+                }
+            }
+            self.after_path_sep = op == "::";
+            self.last_ident.clear();
+            offset += len;
+        }
+    }
+    /// Guesses whether a `<` written next opens a list of generic arguments
+    /// or parameters, rather than being a comparison.
+    ///
+    /// This can't be known without parsing, so the heuristic is that a `<`
+    /// is generic after a path separator, after the name in an item
+    /// declaration, after `impl` or `for`, after an identifier that looks
+    /// like a type name, or anywhere it couldn't be a binary operator.
+    fn opens_generics(&self) -> bool {
+        if self.after_path_sep {
+            return true;
+        }
+        let ident = self.last_ident.as_str();
+        match self.prev {
+            Prev::Word => {
+                !ident.is_empty() && (self.after_item_keyword || looks_like_type_name(ident))
+            }
+            Prev::Keyword => ident == "impl" || ident == "for",
+            Prev::Open | Prev::SpacedPunct | Prev::TightPunct => true,
+        }
+    }
+    fn visit_token_tree(&mut self, token_tree: TokenTree) {
+        if let TokenTree::Punct(punct) = token_tree {
+            let spacing = punct.spacing();
+            self.puncts.push(punct);
+            if spacing == Spacing::Alone {
+                self.flush_puncts();
+            }
+            return;
+        }
+        self.flush_puncts();
+        self.after_path_sep = false;
+        let last_ident = std::mem::take(&mut self.last_ident);
+        let after_lifetime_quote = std::mem::take(&mut self.after_lifetime_quote);
+        self.after_item_keyword = false;
+        match token_tree {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    // Invisible groups, such as those around a substituted
+                    // `$expr`, are written out as just their contents, but
+                    // the contents are spaced as a unit of their own.
+                    Delimiter::None => {
+                        let angle_depth = std::mem::take(&mut self.angle_depth);
+                        let in_closure_params = std::mem::take(&mut self.in_closure_params);
+                        self.visit_token_stream(group.stream());
+                        self.flush_puncts();
+                        self.angle_depth = angle_depth;
+                        self.in_closure_params = in_closure_params;
+                        return;
+                    }
+                };
+                // The body of an item ends its `where` clause, and starts on
+                // a line of its own.
+                if open == "{" && self.in_where {
+                    self.end_where();
+                    self.end_line();
+                }
+                // A parenthesis or bracket directly after a word is a call or
+                // an index.
+                self.space_before(open == "{" || self.prev != Prev::Word, 1);
+                self.visit_str(open, group.span_open(), TokenKind::Group, None);
+                self.set_prev(Prev::Open);
+                let angle_depth = std::mem::take(&mut self.angle_depth);
+                let in_closure_params = std::mem::take(&mut self.in_closure_params);
+                let in_where = std::mem::take(&mut self.in_where);
+                self.visit_token_stream(group.stream());
+                self.flush_puncts();
+                self.end_where();
+                self.angle_depth = angle_depth;
+                self.in_closure_params = in_closure_params;
+                self.in_where = in_where;
+                self.visit_str(close, group.span_close(), TokenKind::Group, None);
+                self.set_prev(Prev::Word);
+            }
+            TokenTree::Ident(ident) => {
+                // Within the compiler, the `$crate` of a macro expansion is a
+                // single identifier, written as it was in the macro.
+                let s = ident.to_string();
+                let highlight = if after_lifetime_quote {
+                    Some(Highlight::Lifetime)
+                } else if KEYWORDS.contains(&s.as_str()) || s == "$crate" {
+                    Some(Highlight::Keyword)
+                } else {
+                    None
+                };
+                let breaks_where =
+                    s == "where" && self.config.break_where_clauses && self.angle_depth == 0;
+                if breaks_where {
+                    self.end_where();
+                    self.end_line();
+                }
+                self.space_before(true, s.chars().count());
+                self.visit_str(&s, ident.span(), TokenKind::Ident, highlight);
+                self.set_prev(if EXPR_KEYWORDS.contains(&s.as_str()) {
+                    Prev::Keyword
+                } else {
+                    Prev::Word
+                });
+                if breaks_where {
+                    self.end_line();
+                    self.in_where = true;
+                    self.indent += 1;
+                }
+                self.after_item_keyword = ITEM_KEYWORDS.contains(&last_ident.as_str());
+                self.last_ident = s;
+            }
+            TokenTree::Literal(literal) => {
+                let s = if self.config.original_literals || self.config.preserve_formatting {
+                    self.original_text(literal.span())
+                } else {
+                    None
+                }
+                .unwrap_or_else(|| literal.to_string());
+                self.space_before(true, s.chars().count());
+                self.visit_literal(&s, literal.span());
+                self.set_prev(Prev::Word);
+            }
+            TokenTree::Punct(_) => unreachable!(),
+        }
+    }
+}
+
+/// Returns whether `text` consists only of whitespace and comments.
+pub(crate) fn is_trivia(mut text: &str) -> bool {
+    loop {
+        text = text.trim_start();
+        if text.is_empty() {
+            return true;
+        } else if let Some(rest) = text.strip_prefix("//") {
+            text = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if text.starts_with("/*") {
+            // Block comments nest.
+            let mut depth = 0;
+            let mut end = None;
+            let mut offset = 0;
+            while offset < text.len() {
+                let rest = &text[offset..];
+                if rest.starts_with("/*") {
+                    depth += 1;
+                    offset += 2;
+                } else if rest.starts_with("*/") {
+                    depth -= 1;
+                    offset += 2;
+                    if depth == 0 {
+                        end = Some(offset);
+                        break;
+                    }
+                } else {
+                    offset += rest.chars().next().map_or(1, char::len_utf8);
+                }
+            }
+            match end {
+                Some(end) => text = &text[end..],
+                None => return false,
+            }
+        } else {
+            return false;
+        }
+    }
+}
+
+/// Returns the byte offsets at which the contents of a string, byte string or
+/// character literal start and end, excluding its prefix, quotes and any
+/// suffix. Returns `None` for literals without quotes, such as numbers.
+///
+/// This is where literals are split into parts mapped to their own source
+/// positions; finer parts, such as escape sequences, would be found here too.
+pub(crate) fn literal_delimiters(lit: &str) -> Option<(usize, usize)> {
+    let start = lit.find(['"', '\''])?;
+    let quote = &lit[start..start + 1];
+    let end = lit.rfind(quote)?;
+    (end > start).then_some((start + 1, end))
+}
+
+/// Returns the value of a string literal, or `None` if `lit` isn't one.
+pub(crate) fn parse_str_literal(lit: &str) -> Option<String> {
+    if let Some(raw) = lit.strip_prefix('r') {
+        let hashes = &raw[..raw.len() - raw.trim_start_matches('#').len()];
+        let value = raw[hashes.len()..]
+            .strip_prefix('"')?
+            .strip_suffix(hashes)?
+            .strip_suffix('"')?;
+        return Some(value.into());
+    }
+    let mut chars = lit.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut res = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => res.push('\n'),
+            'r' => res.push('\r'),
+            't' => res.push('\t'),
+            '0' => res.push('\0'),
+            c @ ('\\' | '\'' | '"') => res.push(c),
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                res.push(u8::from_str_radix(&digits, 16).ok()?.into());
+            }
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let code = u32::from_str_radix(&rest[..end].replace('_', ""), 16).ok()?;
+                res.push(char::from_u32(code)?);
+                chars = rest[end + 1..].chars();
+            }
+            '\n' => chars = chars.as_str().trim_start().chars(),
+            _ => return None,
+        }
+    }
+    Some(res)
+}
+
+impl<'a> TokenVisitor<'a> {
+    pub(crate) fn new(config: &'a Spanr) -> Self {
+        TokenVisitor {
+            config,
+            ranges: Default::default(),
+            indent: 0,
+            newline: true,
+            prev: Prev::Open,
+            puncts: Vec::new(),
+            angle_depth: 0,
+            after_path_sep: false,
+            last_ident: String::new(),
+            after_item_keyword: false,
+            after_lifetime_quote: false,
+            in_closure_params: false,
+            column: 0,
+            continuation: false,
+            last_source: None,
+            blocks: Vec::new(),
+            block_count: 0,
+            in_where: false,
+            source_lines: HashMap::new(),
+            in_root: HashMap::new(),
+            row_sink: None,
+        }
+    }
+    /// Writes out a whole token stream, formatted by prettyplease if enabled.
+    pub(crate) fn visit_root(&mut self, token_stream: TokenStream) {
+        #[cfg(feature = "prettyplease")]
+        if self.config.prettyplease && self.visit_formatted(token_stream.clone()) {
+            self.newline = self
+                .ranges
+                .generated
+                .last()
+                .is_none_or(|chunk| chunk.text == "\n");
+            return;
+        }
+        self.visit_token_stream(token_stream);
+        self.flush_puncts();
+    }
+    /// Writes out a token stream as its own section, headed by `label`.
+    fn visit_section(&mut self, label: &str, token_stream: TokenStream) {
+        if !self.ranges.generated.is_empty() {
+            self.add_str("\n");
+        }
+        let header = format!("// {}", label);
+        for line in [SECTION_RULE, &header, SECTION_RULE] {
+            self.add_str(line);
+            self.add_str("\n");
+        }
+        self.visit_root(token_stream);
+        if !self.newline {
+            self.add_str("\n");
+        }
+        self.indent = 0;
+        self.newline = true;
+        self.prev = Prev::Open;
+        self.angle_depth = 0;
+        self.in_where = false;
+    }
+}
+
+pub(crate) fn generate_ranges(token_stream: TokenStream, config: &Spanr) -> Ranges {
+    let mut res = TokenVisitor::new(config);
+    res.visit_root(token_stream);
+    if config.preserve_formatting {
+        res.restore_source_spacing();
+    }
+    res.ranges.sort();
+    res.ranges
+}
+
+/// Like [`generate_ranges`], but writes out each token stream as a separate
+/// labeled section, sharing one table of sources and ranges.
+pub(crate) fn generate_ranges_multi(
+    streams: impl IntoIterator<Item = (String, TokenStream)>,
+    config: &Spanr,
+) -> Ranges {
+    let mut res = TokenVisitor::new(config);
+    for (label, token_stream) in streams {
+        res.visit_section(&label, token_stream);
+    }
+    if config.preserve_formatting {
+        res.restore_source_spacing();
+    }
+    res.ranges.sort();
+    res.ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::{Span, TokenStream, TokenTree};
+
+    use crate::Position;
+
+    #[test]
+    fn trivia() {
+        assert!(super::is_trivia(" // a\n /* b /* c */ */\n"));
+        assert!(!super::is_trivia(" /* a "));
+        assert!(!super::is_trivia(" a "));
+    }
+
+    #[test]
+    fn unjoined_puncts() {
+        // Punctuation that isn't joint forms separate operators.
+        let config = super::Spanr::new();
+        let mut visitor = super::TokenVisitor::new(&config);
+        let tokens: TokenStream = vec![
+            TokenTree::from(proc_macro2::Ident::new("x", Span::call_site())),
+            proc_macro2::Punct::new('+', proc_macro2::Spacing::Alone).into(),
+            proc_macro2::Punct::new('=', proc_macro2::Spacing::Alone).into(),
+            proc_macro2::Literal::u8_unsuffixed(1).into(),
+        ]
+        .into_iter()
+        .collect();
+        visitor.visit_token_stream(tokens);
+        visitor.flush_puncts();
+        let text: String = visitor
+            .ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(text, "x + = 1");
+    }
+
+    #[test]
+    fn unbalanced_close_brace() {
+        let config = super::Spanr::new();
+        let mut visitor = super::TokenVisitor::new(&config);
+        visitor.visit_str("}", Span::call_site(), super::TokenKind::Group, None);
+        visitor.visit_token_stream("x".parse().unwrap());
+        visitor.flush_puncts();
+        let text: String = visitor
+            .ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(text, "}\nx");
+    }
+
+    #[test]
+    fn group_by_source() {
+        let config = super::Spanr::new().group_by_source(true);
+        let mut visitor = super::TokenVisitor::new(&config);
+        let pos = Position { line: 1, column: 0 };
+        let a = visitor.ranges.add_range("a.rs".into(), pos, pos);
+        let b = visitor.ranges.add_range("b.rs".into(), pos, pos);
+        for (text, idx) in [("x", a), ("y", a), ("z", b)] {
+            visitor.label_source(Some(idx));
+            visitor.start_line();
+            visitor.add_token(text, Some(idx), None);
+            visitor.add_str(" ");
+        }
+        let generated: String = visitor
+            .ranges
+            .generated
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect();
+        assert_eq!(generated, "// --- a.rs ---\nx y\n// --- b.rs ---\nz ");
+        assert!(visitor
+            .ranges
+            .generated
+            .iter()
+            .filter(|chunk| chunk.text.starts_with("//"))
+            .all(|chunk| chunk.range.is_none()));
+    }
+
+    #[test]
+    fn str_literals() {
+        use super::parse_str_literal;
+        assert_eq!(
+            parse_str_literal(r#"" a \"b\"\n\u{1F980}""#).unwrap(),
+            " a \"b\"\n🦀"
+        );
+        assert_eq!(parse_str_literal(r###"r#"x "y""#"###).unwrap(), "x \"y\"");
+        assert_eq!(parse_str_literal("'a'"), None);
+        assert_eq!(parse_str_literal("b\"a\""), None);
+    }
+}